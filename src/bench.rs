@@ -0,0 +1,347 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+    process::Stdio,
+    time::Instant,
+};
+
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::process::Command;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub programs: Vec<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default = "default_num_signatures")]
+    pub num_signatures: Vec<usize>,
+    #[serde(default = "default_flags")]
+    pub fix: Vec<bool>,
+    #[serde(default = "default_flags")]
+    pub augmentation: Vec<bool>,
+    #[serde(default = "default_flags")]
+    pub stage: Vec<bool>,
+    #[serde(default)]
+    pub jobs: Option<usize>,
+}
+
+fn default_num_signatures() -> Vec<usize> {
+    vec![3]
+}
+
+fn default_flags() -> Vec<bool> {
+    vec![true]
+}
+
+impl Manifest {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let manifest = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        serde_json::from_str(&manifest)
+            .map_err(|e| format!("invalid manifest {}: {}", path.display(), e))
+    }
+
+    pub fn experiments(&self) -> Vec<Experiment> {
+        let mut experiments = vec![];
+        for num_signatures in &self.num_signatures {
+            for fix in &self.fix {
+                for augmentation in &self.augmentation {
+                    for stage in &self.stage {
+                        for program in &self.programs {
+                            experiments.push(Experiment {
+                                program: expand_home(program),
+                                num_signatures: *num_signatures,
+                                fix: *fix,
+                                augmentation: *augmentation,
+                                stage: *stage,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        experiments
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Experiment {
+    pub program: String,
+    pub num_signatures: usize,
+    pub fix: bool,
+    pub augmentation: bool,
+    pub stage: bool,
+}
+
+impl Experiment {
+    fn args(&self, report: &Path) -> Vec<String> {
+        let mut args = vec![
+            "-q".to_string(),
+            "--report".to_string(),
+            report.to_string_lossy().to_string(),
+        ];
+        if self.num_signatures == 0 {
+            args.push("--no-candidate".to_string());
+        } else {
+            args.push("--num-signatures".to_string());
+            args.push(self.num_signatures.to_string());
+        }
+        if !self.fix {
+            args.push("--no-fix".to_string());
+        }
+        if !self.augmentation {
+            args.push("--no-augmentation".to_string());
+        }
+        if !self.stage {
+            args.push("--no-stage".to_string());
+        }
+        args
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchResult {
+    pub experiment: Experiment,
+    pub success: bool,
+    pub time: f32,
+    pub metrics: BTreeMap<String, Value>,
+}
+
+pub async fn run<P: AsRef<Path>>(
+    exe: P,
+    manifest: &Manifest,
+    jobs: Option<usize>,
+) -> Vec<BenchResult> {
+    let exe = exe.as_ref();
+    let jobs = jobs.or(manifest.jobs).unwrap_or(1).max(1);
+    let dir = std::env::temp_dir().join(format!("simcrat-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let experiments = manifest.experiments();
+    let n = experiments.len();
+    let results: Vec<_> = stream::iter(experiments.into_iter().enumerate())
+        .map(|(i, experiment)| {
+            let report = dir.join(format!("{}.json", i));
+            async move {
+                let result = run_experiment(exe, &manifest.args, experiment, &report).await;
+                println!(
+                    "[{}/{}] {} ({})",
+                    i + 1,
+                    n,
+                    result.experiment.program,
+                    if result.success { "done" } else { "failed" }
+                );
+                (i, result)
+            }
+        })
+        .buffer_unordered(jobs)
+        .collect()
+        .await;
+    let _ = fs::remove_dir_all(dir);
+
+    let mut results: BTreeMap<_, _> = results.into_iter().collect();
+    (0..n).map(|i| results.remove(&i).unwrap()).collect()
+}
+
+async fn run_experiment(
+    exe: &Path,
+    args: &[String],
+    experiment: Experiment,
+    report: &Path,
+) -> BenchResult {
+    let start = Instant::now();
+    let output = Command::new(exe)
+        .args(args)
+        .args(experiment.args(report))
+        .arg(&experiment.program)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .unwrap();
+    let time = start.elapsed().as_secs_f32();
+
+    let mut metrics = BTreeMap::new();
+    let report = fs::read_to_string(report)
+        .ok()
+        .and_then(|s| serde_json::from_str::<Value>(&s).ok());
+    let success = output.status.success() && report.is_some();
+    if let Some(report) = report {
+        flatten_metrics("", &report, &mut metrics);
+    } else {
+        eprintln!(
+            "{}\n{}",
+            experiment.program,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    BenchResult {
+        experiment,
+        success,
+        time,
+        metrics,
+    }
+}
+
+fn flatten_metrics(prefix: &str, value: &Value, metrics: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map {
+                if SKIPPED_METRICS.contains(&k.as_str()) {
+                    continue;
+                }
+                let key = if prefix.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", prefix, k)
+                };
+                flatten_metrics(&key, v, metrics);
+            }
+        }
+        Value::Number(_) | Value::Bool(_) => {
+            metrics.insert(prefix.to_string(), value.clone());
+        }
+        _ => (),
+    }
+}
+
+pub fn write_results<P: AsRef<Path>>(path: P, results: &[BenchResult]) {
+    let path = path.as_ref();
+    if path.extension().map_or(false, |e| e == "csv") {
+        fs::write(path, to_csv(results)).unwrap();
+    } else {
+        fs::write(path, serde_json::to_string_pretty(results).unwrap()).unwrap();
+    }
+}
+
+fn to_csv(results: &[BenchResult]) -> String {
+    let columns: BTreeSet<_> = results.iter().flat_map(|r| r.metrics.keys()).collect();
+    let mut csv = String::from("program,num_signatures,fix,augmentation,stage,success,time");
+    for column in &columns {
+        csv.push(',');
+        csv.push_str(column);
+    }
+    csv.push('\n');
+    for r in results {
+        let e = &r.experiment;
+        csv.push_str(&format!(
+            "\"{}\",{},{},{},{},{},{}",
+            e.program.replace('"', "\"\""),
+            e.num_signatures,
+            e.fix,
+            e.augmentation,
+            e.stage,
+            r.success,
+            r.time
+        ));
+        for column in &columns {
+            csv.push(',');
+            if let Some(v) = r.metrics.get(*column) {
+                csv.push_str(&v.to_string());
+            }
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(path), Ok(home)) => format!("{}/{}", home, path),
+        _ => path.to_string(),
+    }
+}
+
+static SKIPPED_METRICS: [&str; 3] = ["items", "rust_types", "c_types"];
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn result(program: &str, report: Value) -> BenchResult {
+        let mut metrics = BTreeMap::new();
+        flatten_metrics("", &report, &mut metrics);
+        BenchResult {
+            experiment: Experiment {
+                program: program.to_string(),
+                num_signatures: 3,
+                fix: true,
+                augmentation: false,
+                stage: true,
+            },
+            success: true,
+            time: 1.5,
+            metrics,
+        }
+    }
+
+    #[test]
+    fn test_flatten_metrics() {
+        let report = json!({
+            "usage": { "request_tokens": 10, "response_time": 0.5 },
+            "errors": { "functions": { "errors": 2, "no_trans_error_names": ["f"] } },
+            "items": [{ "c_name": "f" }],
+            "types": { "rust_types": { "i32": 1 }, "c_types": { "int": 1 }, "unsafe": 3 },
+            "failed": false,
+            "name": "x"
+        });
+        let mut metrics = BTreeMap::new();
+        flatten_metrics("", &report, &mut metrics);
+        let expected: BTreeMap<_, _> = [
+            ("errors.functions.errors", json!(2)),
+            ("failed", json!(false)),
+            ("types.unsafe", json!(3)),
+            ("usage.request_tokens", json!(10)),
+            ("usage.response_time", json!(0.5)),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+        assert_eq!(metrics, expected);
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let results = [
+            result("a\"b.json", json!({ "x": 1 })),
+            result("c.json", json!({ "y": { "z": true } })),
+        ];
+        let csv = to_csv(&results);
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "program,num_signatures,fix,augmentation,stage,success,time,x,y.z",
+                "\"a\"\"b.json\",3,true,false,true,true,1.5,1,",
+                "\"c.json\",3,true,false,true,true,1.5,,true",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_manifest_from_file() {
+        let dir = std::env::temp_dir().join(format!("simcrat-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("manifest.json");
+        fs::write(&path, r#"{ "programs": ["a.json"], "fix": [true, false] }"#).unwrap();
+        let manifest = Manifest::from_file(&path).unwrap();
+        assert_eq!(manifest.num_signatures, [3]);
+        assert_eq!(manifest.experiments().len(), 2);
+
+        fs::write(&path, r#"{ "fix": [true] }"#).unwrap();
+        let err = Manifest::from_file(&path).unwrap_err();
+        assert!(err.starts_with("invalid manifest"), "{}", err);
+
+        let err = Manifest::from_file(dir.join("missing.json")).unwrap_err();
+        assert!(err.starts_with("cannot read"), "{}", err);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
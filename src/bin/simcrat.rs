@@ -1,21 +1,48 @@
-use std::{fs::File, io::Write, time::Instant};
+use std::{collections::BTreeMap, fs::File, io::Write, time::Instant};
 
-use clap::Parser;
-use simcrat::*;
+use clap::{Parser, Subcommand};
+use simcrat::{config::Backend, *};
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    Bench {
+        manifest: String,
+        #[arg(short, long)]
+        output: String,
+        #[arg(short, long)]
+        jobs: Option<usize>,
+    },
+}
 
 #[derive(Parser, Debug)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long)]
+    config: Option<String>,
+
     #[arg(short, long)]
     log_file: Option<String>,
 
-    // #[arg(long)]
-    // local_url: Option<String>,
+    #[arg(long, value_enum)]
+    backend: Option<Backend>,
+    #[arg(long)]
+    local_url: Option<String>,
+    #[arg(long)]
+    local_max_prompt_tokens: Option<usize>,
+    #[arg(long, value_parser = positive)]
+    concurrency: Option<usize>,
+    #[arg(long)]
+    retries: Option<usize>,
+
     #[arg(short, long)]
     api_key_file: Option<String>,
     #[arg(short, long)]
     model: Option<String>,
     #[arg(long)]
-    max_tokens: usize,
+    max_tokens: Option<usize>,
 
     #[arg(long)]
     db_name: Option<String>,
@@ -26,21 +53,76 @@ struct Args {
     #[arg(long)]
     db_password: Option<String>,
 
-    #[arg(long)]
+    #[arg(long, overrides_with = "no_candidate")]
+    candidate: bool,
+    #[arg(long, overrides_with = "candidate")]
     no_candidate: bool,
-    #[arg(long)]
+    #[arg(long, overrides_with = "no_augmentation")]
+    augmentation: bool,
+    #[arg(long, overrides_with = "augmentation")]
     no_augmentation: bool,
-    #[arg(long)]
+    #[arg(long, overrides_with = "no_fix")]
+    fix: bool,
+    #[arg(long, overrides_with = "fix")]
     no_fix: bool,
-    #[arg(long)]
+    #[arg(long, overrides_with = "no_stage")]
+    stage: bool,
+    #[arg(long, overrides_with = "stage")]
     no_stage: bool,
+    #[arg(long, overrides_with = "no_macro")]
+    r#macro: bool,
+    #[arg(long, overrides_with = "macro")]
+    no_macro: bool,
+    #[arg(long, overrides_with = "no_comments")]
+    comments: bool,
+    #[arg(long, overrides_with = "comments")]
+    no_comments: bool,
     #[arg(long)]
     num_signatures: Option<usize>,
+    #[arg(long)]
+    max_fixes: Option<usize>,
+
+    #[arg(long)]
+    preprocessor: Option<String>,
+    #[arg(long, allow_hyphen_values = true)]
+    preprocessor_arg: Vec<String>,
+
+    #[arg(short = 'I', long)]
+    include_dir: Vec<String>,
+    #[arg(short = 'D', long)]
+    define: Vec<String>,
+
+    #[arg(long)]
+    library_path: Vec<String>,
+    #[arg(long)]
+    project_path: Vec<String>,
+
+    #[arg(long)]
+    only_function: Vec<String>,
+    #[arg(long)]
+    only_file: Vec<String>,
+    #[arg(long)]
+    only_type: Vec<String>,
+
+    #[arg(long)]
+    checkpoint_dir: Option<String>,
+    #[arg(long, requires("checkpoint_dir"))]
+    resume: bool,
 
     #[arg(long)]
     parsing_only: bool,
     #[arg(long)]
+    estimate: bool,
+    #[arg(long)]
+    price_table: Option<String>,
+    #[arg(long, overrides_with = "no_real_time")]
     real_time: bool,
+    #[arg(long, overrides_with = "real_time")]
+    no_real_time: bool,
+    #[arg(long, overrides_with = "no_replay")]
+    replay: bool,
+    #[arg(long, overrides_with = "replay")]
+    no_replay: bool,
 
     #[arg(long)]
     show_time: bool,
@@ -56,22 +138,65 @@ struct Args {
     // show_signature: bool,
     #[arg(long)]
     show_type: bool,
+    #[arg(long)]
+    show_headers: bool,
 
     #[arg(short, long)]
     detail: bool,
     #[arg(short, long)]
     quiet: bool,
 
+    #[arg(long)]
+    report: Option<String>,
+    #[arg(long)]
+    provenance: Option<String>,
+    #[arg(long, overrides_with = "no_provenance_comments")]
+    provenance_comments: bool,
+    #[arg(long, overrides_with = "provenance_comments")]
+    no_provenance_comments: bool,
     #[arg(short, long)]
     output: Option<String>,
-    input: String,
+    #[arg(long)]
+    output_dir: Option<String>,
+    #[arg(required = true)]
+    input: Vec<String>,
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
 
-    if let Some(log) = args.log_file {
+    if let Some(Command::Bench {
+        manifest,
+        output,
+        jobs,
+    }) = args.command
+    {
+        let exe = std::env::current_exe().unwrap();
+        let manifest = match bench::Manifest::from_file(manifest) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        let results = bench::run(exe, &manifest, jobs).await;
+        bench::write_results(output, &results);
+        return;
+    }
+
+    let file_conf = match config::FileConfig::load(args.config.as_deref()) {
+        Ok(file_conf) => file_conf,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let client_conf = file_conf.client;
+    let db_file_conf = file_conf.db;
+    let trans_conf = file_conf.translation;
+
+    if let Some(log) = args.log_file.or(file_conf.log_file) {
         let log_file = File::create(log).unwrap();
         tracing_subscriber::fmt()
             .with_max_level(tracing::Level::INFO)
@@ -80,32 +205,165 @@ async fn main() {
             .init();
     }
 
-    let model = args.model;
-    let api_key = args.api_key_file;
+    let model = args.model.or(client_conf.model);
+    let prices = llm_client::price_table(args.price_table.as_deref());
+    let price = prices
+        .get(model.as_deref().unwrap_or("gpt-3.5-turbo"))
+        .copied();
+    let api_key = args.api_key_file.or(client_conf.api_key_file);
+    let retries = args.retries.or(client_conf.retries).unwrap_or(10);
+    if retries == 0 {
+        eprintln!("retries must be positive");
+        std::process::exit(1);
+    }
     let db_conf = llm_client::cache::DbConfig {
-        name: args.db_name,
-        host: args.db_host,
-        port: args.db_port,
-        password: args.db_password,
-        real_time: args.real_time,
+        name: args.db_name.or(db_file_conf.name),
+        host: args.db_host.or(db_file_conf.host),
+        port: args.db_port.or(db_file_conf.port),
+        password: args.db_password.or(db_file_conf.password),
+        real_time: flag(args.real_time, args.no_real_time)
+            .or(db_file_conf.real_time)
+            .unwrap_or(false),
+        replay: flag(args.replay, args.no_replay)
+            .or(db_file_conf.replay)
+            .unwrap_or(false),
     };
     let config = translation::Config {
-        max_tokens: args.max_tokens,
-        try_multiple_signatures: !args.no_candidate,
-        num_signatures: args.num_signatures.unwrap_or(3),
-        provide_signatures: !args.no_augmentation,
-        fix_errors: !args.no_fix,
-        consider_stages: !args.no_stage,
+        max_tokens: args
+            .max_tokens
+            .or(trans_conf.max_tokens)
+            .expect("max_tokens must be given by --max-tokens or the config file"),
+        try_multiple_signatures: flag(args.candidate, args.no_candidate)
+            .or(trans_conf.candidate)
+            .unwrap_or(true),
+        num_signatures: args
+            .num_signatures
+            .or(trans_conf.num_signatures)
+            .unwrap_or(3),
+        provide_signatures: flag(args.augmentation, args.no_augmentation)
+            .or(trans_conf.augmentation)
+            .unwrap_or(true),
+        fix_errors: flag(args.fix, args.no_fix)
+            .or(trans_conf.fix)
+            .unwrap_or(true),
+        consider_stages: flag(args.stage, args.no_stage)
+            .or(trans_conf.stage)
+            .unwrap_or(true),
+        max_fixes: args.max_fixes.or(trans_conf.max_fixes).unwrap_or(20),
+        recover_macros: flag(args.r#macro, args.no_macro)
+            .or(trans_conf.macros)
+            .unwrap_or(false),
+        prompt_comments: flag(args.comments, args.no_comments)
+            .or(trans_conf.comments)
+            .unwrap_or(false),
+        provenance_comments: flag(args.provenance_comments, args.no_provenance_comments)
+            .or(trans_conf.provenance_comments)
+            .unwrap_or(false),
         quiet: args.quiet,
     };
 
     let start = Instant::now();
 
-    let prog = c_parser::Program::from_compile_commands(&args.input);
-    let client: Box<dyn llm_client::LanguageModel + Send + Sync> = Box::new(
-        llm_client::openai::OpenAIClient::new(model, api_key, db_conf),
-    );
+    let mut preprocessor = file_conf.preprocessor;
+    if let Some(command) = args.preprocessor {
+        preprocessor.command = command;
+    }
+    preprocessor.extra_args.extend(args.preprocessor_arg);
+    let mut library_filter = file_conf.library;
+    library_filter.paths.extend(args.library_path);
+    library_filter.exclude.extend(args.project_path);
+    let prog = match &args.input[..] {
+        [input] if input.ends_with(".json") => {
+            c_parser::Program::from_compile_commands(input, &preprocessor, &library_filter)
+        }
+        inputs => {
+            let flags: Vec<_> = args
+                .include_dir
+                .iter()
+                .map(|dir| format!("-I{}", dir))
+                .chain(args.define.iter().map(|define| format!("-D{}", define)))
+                .collect();
+            c_parser::Program::from_sources(inputs, &flags, &preprocessor, &library_filter)
+        }
+    };
+    for diagnostic in &prog.diagnostics {
+        eprintln!("skipped {}: {}", diagnostic.path, diagnostic.message);
+        if !diagnostic.unresolved.is_empty() {
+            eprintln!("  unresolved: {}", diagnostic.unresolved.join(", "));
+        }
+    }
+    let backend = args
+        .backend
+        .or(client_conf.backend)
+        .unwrap_or(Backend::Openai);
+    let concurrency = args.concurrency.or(client_conf.concurrency);
+    let client: Box<dyn llm_client::LanguageModel + Send + Sync> = match backend {
+        Backend::Openai => {
+            let mut client = llm_client::openai::OpenAIClient::new(
+                model,
+                api_key,
+                concurrency.unwrap_or(30),
+                retries,
+                db_conf,
+            );
+            if args.estimate {
+                client.enable_dry_run();
+            }
+            Box::new(client)
+        }
+        Backend::Local => {
+            let mut client = llm_client::local::LocalClient::new(
+                args.local_url
+                    .or(client_conf.local_url)
+                    .expect("local_url must be given for the local backend"),
+                concurrency.unwrap_or(1),
+                args.local_max_prompt_tokens
+                    .or(client_conf.local_max_prompt_tokens)
+                    .unwrap_or(2047),
+                db_conf,
+            );
+            if args.estimate {
+                client.enable_dry_run();
+            }
+            Box::new(client)
+        }
+    };
     let mut translator = translation::Translator::new(&prog, client, config);
+    translator.select(&translation::Selection {
+        functions: args.only_function,
+        files: args.only_file,
+        types: args.only_type,
+    });
+    if let Some(checkpoint_dir) = args.checkpoint_dir {
+        // an estimate only reads the checkpoint to skip restored items
+        if !args.estimate || args.resume {
+            translator.enable_checkpoint(checkpoint_dir, args.resume);
+        }
+    }
+
+    if args.estimate {
+        let estimate = translator.estimate().await;
+        for (phase, e) in estimate.phases() {
+            println!(
+                "{}: {} requests, {} request tokens, {} response tokens",
+                phase, e.requests, e.request_tokens, e.response_tokens
+            );
+        }
+        let total = estimate.total();
+        println!(
+            "total: {} requests, {} request tokens, {} response tokens",
+            total.requests, total.request_tokens, total.response_tokens
+        );
+        println!("too long: {}", estimate.too_long);
+        if let Some(price) = price {
+            println!("cost: ${:.2}", total.cost(&price));
+        }
+        return;
+    }
+
+    if args.show_headers {
+        translator.show_headers();
+    }
 
     if args.parsing_only {
         if args.show_program_size {
@@ -118,6 +376,7 @@ async fn main() {
     translator.translate_types().await;
     translator.translate_variables().await;
     translator.translate_protos().await;
+    translator.translate_macros().await;
     translator.translate_functions().await;
 
     if args.show_program_size {
@@ -140,8 +399,56 @@ async fn main() {
         println!("{}", start.elapsed().as_secs_f32());
     }
 
+    if let Some(report) = args.report {
+        let f = File::create(report).unwrap();
+        serde_json::to_writer_pretty(f, &translator.report()).unwrap();
+    }
+
+    if let Some(provenance) = args.provenance {
+        let f = File::create(provenance).unwrap();
+        serde_json::to_writer_pretty(f, &translator.provenance()).unwrap();
+    }
+
     if let Some(output) = args.output {
         let mut f = File::create(output).unwrap();
         f.write_all(translator.code().as_bytes()).unwrap();
     }
+
+    if let Some(output_dir) = args.output_dir {
+        translator.write_project(output_dir);
+    }
+
+    let misses = translator.cache_misses();
+    if !misses.is_empty() {
+        let mut kinds: BTreeMap<_, usize> = BTreeMap::new();
+        for miss in &misses {
+            *kinds.entry(miss.kind.as_str()).or_default() += 1;
+        }
+        eprintln!("{} cache misses", misses.len());
+        for (kind, n) in kinds {
+            eprintln!("  {}: {}", kind, n);
+        }
+        std::process::exit(1);
+    }
+}
+
+/// Returns the value set by a `--x`/`--no-x` pair, where the last one given
+/// wins, or `None` if neither is given.
+fn flag(yes: bool, no: bool) -> Option<bool> {
+    if yes {
+        Some(true)
+    } else if no {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Parses a positive integer argument, e.g., `--concurrency`.
+fn positive(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("must be positive".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("{}", e)),
+    }
 }
@@ -1,12 +1,15 @@
 use std::{
-    collections::{BTreeMap, BTreeSet},
-    fmt,
-    fs::File,
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    env, fmt,
+    fs::{self, File},
     io::BufReader,
+    ops::Range,
     path::{Path, PathBuf},
     process::Command,
+    thread,
 };
 
+use etrace::some_or;
 use lang_c::{
     ast::*,
     driver::{self, Config, Parse},
@@ -17,7 +20,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::compiler::{self, FunTySig, Type};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
 pub enum TypeSort {
     Typedef,
     Struct,
@@ -77,6 +80,14 @@ impl<'ast> CustomType<'ast> {
 pub struct TypeDependency<'ast> {
     pub typ: CustomType<'ast>,
     pub span: Span,
+    /// The enumerator when the dependency is a use of it rather than of the type.
+    pub enumerator: Option<&'ast str>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TermDependency<'ast> {
+    pub name: &'ast str,
+    pub span: Span,
 }
 
 #[derive(Debug)]
@@ -99,9 +110,73 @@ pub struct Struct<'ast> {
     pub name: &'ast str,
     pub struct_type: &'ast Node<StructType>,
     pub dependencies: Vec<TypeDependency<'ast>>,
+    pub bit_fields: Option<BitFields>,
     pub path: &'ast str,
 }
 
+/// The bit-fields of a struct, packed into backing integers in declaration order. A bit-field
+/// starts a new backing integer when it does not fit in the current one or follows a zero-width
+/// bit-field, and a backing integer is as wide as the widest type declared for its bit-fields.
+#[derive(Debug, Clone)]
+pub struct BitFields {
+    /// The spans of consecutive bit-field declarations and the backing integers replacing them.
+    pub runs: Vec<(Span, Range<usize>)>,
+    /// The width of each backing integer in bits.
+    pub units: Vec<usize>,
+    pub fields: Vec<BitField>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitField {
+    pub name: String,
+    pub unit: usize,
+    pub offset: usize,
+    pub width: usize,
+    /// The width of the declared type in bits.
+    pub bits: usize,
+    pub signed: bool,
+    pub boolean: bool,
+}
+
+impl BitFields {
+    pub fn backing_field(unit: usize) -> String {
+        format!("bitfield_{}", unit)
+    }
+
+    /// Returns the C declarations of the backing integers replacing each run, with comments
+    /// describing where the bit-fields are stored.
+    pub fn backing_declarations(&self) -> Vec<(Span, String)> {
+        self.runs
+            .iter()
+            .map(|(span, units)| {
+                let decls = units
+                    .clone()
+                    .map(|unit| {
+                        let fields = self
+                            .fields
+                            .iter()
+                            .filter(|f| f.unit == unit)
+                            .map(|f| {
+                                let end = f.offset + f.width - 1;
+                                format!("{}: bits {}..={}", f.name, f.offset, end)
+                            })
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        format!(
+                            "uint{}_t {}; /* {} */",
+                            self.units[unit],
+                            Self::backing_field(unit),
+                            fields
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                (*span, decls)
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug)]
 pub struct Enum<'ast> {
     pub declaration: &'ast Node<Declaration>,
@@ -110,6 +185,67 @@ pub struct Enum<'ast> {
     pub path: &'ast str,
 }
 
+/// Returns the span of the tag and the text replacing it with `new_name`. Anonymous types get
+/// the name inserted after the keyword.
+fn tag_replacement(
+    tag: Option<&Node<Identifier>>,
+    keyword_end: usize,
+    new_name: &str,
+) -> (Span, String) {
+    match tag {
+        Some(id) => (id.span, new_name.to_string()),
+        None => (
+            Span::span(keyword_end, keyword_end),
+            format!(" {}", new_name),
+        ),
+    }
+}
+
+impl Struct<'_> {
+    pub fn tag(&self, new_name: &str) -> (Span, String) {
+        let StructType {
+            kind, identifier, ..
+        } = &self.struct_type.node;
+        tag_replacement(identifier.as_ref(), kind.span.end, new_name)
+    }
+}
+
+impl Enum<'_> {
+    pub fn tag(&self, new_name: &str) -> (Span, String) {
+        let keyword_end = self.enum_type.span.start + "enum".len();
+        tag_replacement(
+            self.enum_type.node.identifier.as_ref(),
+            keyword_end,
+            new_name,
+        )
+    }
+
+    pub fn enumerators(&self) -> Vec<&Node<Identifier>> {
+        enumerator_identifiers(&self.enum_type.node)
+    }
+}
+
+impl Typedef<'_> {
+    /// Returns the enumerators of the anonymous enum defined by the `typedef`.
+    pub fn enumerators(&self) -> Vec<&Node<Identifier>> {
+        self.types
+            .iter()
+            .flat_map(|t| match &t.node {
+                TypeSpecifier::Enum(e) => enumerator_identifiers(&e.node),
+                _ => vec![],
+            })
+            .collect()
+    }
+}
+
+fn enumerator_identifiers(enum_type: &EnumType) -> Vec<&Node<Identifier>> {
+    enum_type
+        .enumerators
+        .iter()
+        .map(|e| &e.node.identifier)
+        .collect()
+}
+
 #[derive(Debug)]
 pub struct Variable<'ast> {
     pub declaration: &'ast Node<Declaration>,
@@ -119,7 +255,9 @@ pub struct Variable<'ast> {
     pub identifier: &'ast Node<Identifier>,
     pub declarator: &'ast Node<InitDeclarator>,
     pub type_dependencies: Vec<TypeDependency<'ast>>,
-    pub dependencies: Vec<&'ast Node<Identifier>>,
+    pub dependencies: Vec<TermDependency<'ast>>,
+    /// Functions stored by the initializer.
+    pub function_pointers: Vec<FunctionPointerUse<'ast>>,
     pub path: &'ast str,
 }
 
@@ -130,12 +268,154 @@ pub struct Function<'ast> {
     pub type_signature: FunTySig,
     pub definition: &'ast Node<FunctionDefinition>,
     pub type_dependencies: Vec<TypeDependency<'ast>>,
-    pub dependencies: Vec<&'ast Node<Identifier>>,
-    pub callees: Vec<&'ast Node<Identifier>>,
+    pub dependencies: Vec<TermDependency<'ast>>,
+    pub callees: Vec<TermDependency<'ast>>,
     pub calls: usize,
+    pub macros: Vec<MacroUse<'ast>>,
+    /// Functions used as values rather than called.
+    pub function_pointers: Vec<FunctionPointerUse<'ast>>,
+    /// The numbers of arguments of the calls through function pointers.
+    pub indirect_calls: Vec<usize>,
+    /// The callees of the calls through pointers to variadic functions, e.g., `o->log`.
+    pub variadic_indirect_calls: Vec<&'ast str>,
     pub path: &'ast str,
 }
 
+/// A function used as a value, e.g., passed as a callback or stored in a dispatch table.
+#[derive(Debug, Clone)]
+pub struct FunctionPointerUse<'ast> {
+    pub function: TermDependency<'ast>,
+    /// How the function is used, e.g., "passed as argument 4 of `qsort` in `main`".
+    pub context: String,
+}
+
+/// A position in an original source file.
+#[derive(Debug, Clone, Serialize)]
+pub struct Location {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+}
+
+/// A `#define` of the project that can be translated to a Rust item.
+#[derive(Debug, Clone)]
+pub struct Macro {
+    pub name: String,
+    pub params: Option<Vec<String>>,
+    pub body: String,
+    /// The translation unit defining the macro.
+    pub path: String,
+    pub location: Option<Location>,
+}
+
+impl Macro {
+    fn parse(define: &str, path: &str) -> Option<Self> {
+        let end = define
+            .find(|c: char| !is_identifier_char(c))
+            .unwrap_or(define.len());
+        let name = define[..end].to_string();
+        let rest = &define[end..];
+        let (params, body) = if let Some(rest) = rest.strip_prefix('(') {
+            let i = rest.find(')')?;
+            let params = if rest[..i].trim().is_empty() {
+                vec![]
+            } else {
+                rest[..i].split(',').map(|p| p.trim().to_string()).collect()
+            };
+            (Some(params), &rest[i + 1..])
+        } else {
+            (None, rest)
+        };
+        let body = body.trim().to_string();
+        let path = path.to_string();
+        Some(Self {
+            name,
+            params,
+            body,
+            path,
+            location: None,
+        })
+    }
+
+    pub fn is_function(&self) -> bool {
+        self.params.is_some()
+    }
+
+    /// Returns why the macro cannot be translated to a constant or a function. Only macros
+    /// expanding to an expression are translated.
+    fn skip_reason(&self, type_names: &BTreeSet<String>) -> Option<&'static str> {
+        let tokens: Vec<_> = tokenize(&self.body).into_iter().map(|(_, t)| t).collect();
+        if !is_identifier(&self.name) || self.name.starts_with('_') {
+            Some("reserved name")
+        } else if tokens.is_empty() {
+            Some("empty")
+        } else if tokens.contains(&"#") {
+            Some("uses `#` or `##`")
+        } else if self.params.iter().flatten().any(|p| !is_identifier(p)) {
+            Some("variadic")
+        } else if !is_expression(&self.body, &tokens, type_names) {
+            Some("not an expression")
+        } else {
+            None
+        }
+    }
+
+    /// Returns the identifiers in the body other than the parameters and the members, each with
+    /// the `struct`, `union`, or `enum` keyword preceding it.
+    pub fn identifiers(&self) -> Vec<(Option<&str>, &str)> {
+        let tokens: Vec<_> = tokenize(&self.body).into_iter().map(|(_, t)| t).collect();
+        let params = self.params.as_deref().unwrap_or(&[]);
+        let mut identifiers = vec![];
+        for (i, token) in tokens.iter().enumerate() {
+            let prev = |n: usize| if i >= n { tokens[i - n] } else { "" };
+            if !is_identifier(token)
+                || KEYWORDS.contains(token)
+                || params.iter().any(|p| p == token)
+                || prev(1) == "."
+                || prev(1) == ">" && prev(2) == "-"
+            {
+                continue;
+            }
+            let tag = Some(prev(1)).filter(|t| matches!(*t, "struct" | "union" | "enum"));
+            identifiers.push((tag, *token));
+        }
+        identifiers
+    }
+
+    pub fn code(&self) -> String {
+        match &self.params {
+            Some(params) => format!("#define {}({}) {}", self.name, params.join(", "), self.body),
+            None => format!("#define {} {}", self.name, self.body),
+        }
+    }
+}
+
+/// The C keywords that can appear in a macro expanding to an expression.
+static KEYWORDS: [&str; 16] = [
+    "char", "const", "double", "enum", "float", "int", "long", "short", "signed", "sizeof",
+    "struct", "union", "unsigned", "void", "volatile", "_Bool",
+];
+
+/// A use of a recovered macro located in the preprocessed code of a function.
+#[derive(Debug, Clone)]
+pub struct MacroUse<'ast> {
+    pub name: &'ast str,
+    /// The span of the expansion.
+    pub span: Span,
+    /// The spans of the arguments in the expansion, in order and strictly inside `span`.
+    pub args: Vec<Span>,
+}
+
+/// A translation unit left out of the program because it could not be preprocessed or parsed.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub path: String,
+    pub message: String,
+    /// Project functions and variables declared but not defined elsewhere that the file mentions.
+    pub unresolved: Vec<String>,
+}
+
 #[derive(Debug)]
 pub struct Program {
     pub parses: BTreeMap<String, Parse>,
@@ -144,83 +424,423 @@ pub struct Program {
     pub variable_set: BTreeSet<String>,
     pub proto_set: BTreeSet<String>,
     pub function_set: BTreeSet<String>,
+    pub local_names: BTreeMap<String, BTreeMap<String, String>>,
+    pub library_headers: BTreeSet<String>,
+    pub project_headers: BTreeSet<String>,
+    pub macros: BTreeMap<String, Macro>,
+    /// The macros of the project that are not translated, with the reasons.
+    pub skipped_macros: BTreeMap<String, String>,
+    /// All the macros defined in the translation units, which expand the uses of `macros`.
+    defines: BTreeMap<String, Macro>,
+    /// Original file, first line, and last line of each function definition.
+    pub function_locations: BTreeMap<String, (PathBuf, usize, usize)>,
+    /// Comments preceding the declarations in the original sources.
+    pub type_comments: BTreeMap<(TypeSort, String), String>,
+    pub term_comments: BTreeMap<String, String>,
+    /// Positions of the declarations in the original sources.
+    pub type_locations: BTreeMap<(TypeSort, String), Location>,
+    pub term_locations: BTreeMap<String, Location>,
+    /// Comments at the beginning of each translation unit, e.g., license headers.
+    pub file_comments: BTreeMap<String, String>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Declarations of the struct fields and variables of function-pointer types by name.
+    pub function_pointer_declarations: BTreeMap<String, BTreeSet<String>>,
+    /// Declarations of the function-pointer fields of each struct, by tag and typedef name.
+    pub function_pointer_fields: BTreeMap<String, Vec<String>>,
+    /// The struct fields and variables that are pointers to variadic functions.
+    pub variadic_function_pointers: BTreeSet<String>,
+    /// The typedefs of pointers to variadic functions.
+    pub variadic_pointer_typedefs: BTreeSet<String>,
+    /// The enum or the `typedef` of an anonymous enum defining each enumerator.
+    pub enumerators: BTreeMap<String, (TypeSort, String)>,
+    /// Tags and typedef names, which the names synthesized for anonymous types avoid.
+    pub declared_type_names: BTreeSet<String>,
 }
 
 impl Program {
-    pub fn from_compile_commands<P: AsRef<Path>>(path: P) -> Self {
+    pub fn from_compile_commands<P: AsRef<Path>>(
+        path: P,
+        preprocessor: &Preprocessor,
+        filter: &LibraryFilter,
+    ) -> Self {
         let file = File::open(path).unwrap();
         let reader = BufReader::new(file);
         let commands: Vec<CompileCommand> = serde_json::from_reader(reader).unwrap();
-        let files: Vec<_> = commands
+        Self::from_commands(commands, preprocessor, filter)
+    }
+
+    /// Builds a program from C files and directories, which are searched recursively for `.c`
+    /// files, without a compilation database. Each file is preprocessed in the current directory
+    /// with `flags`, e.g., `-I` and `-D` options.
+    pub fn from_sources<P: AsRef<Path>>(
+        inputs: &[P],
+        flags: &[String],
+        preprocessor: &Preprocessor,
+        filter: &LibraryFilter,
+    ) -> Self {
+        let directory = env::current_dir().unwrap();
+        let mut files = vec![];
+        for input in inputs {
+            collect_sources(input.as_ref(), &mut files);
+        }
+        let commands = files
             .into_iter()
-            .flat_map(|command| command.preprocess())
+            .map(|file| {
+                let mut arguments = vec!["cc".to_string()];
+                arguments.extend(flags.iter().cloned());
+                arguments.push(file.to_str().unwrap().to_string());
+                CompileCommand {
+                    arguments: Some(arguments),
+                    command: None,
+                    directory: directory.clone(),
+                    file,
+                }
+            })
             .collect();
-        Self::new(files)
+        Self::from_commands(commands, preprocessor, filter)
+    }
+
+    fn from_commands(
+        commands: Vec<CompileCommand>,
+        preprocessor: &Preprocessor,
+        filter: &LibraryFilter,
+    ) -> Self {
+        let jobs = preprocessor
+            .jobs
+            .or_else(|| thread::available_parallelism().ok().map(|n| n.get()))
+            .unwrap_or(1)
+            .max(1);
+        let mut results: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..jobs)
+                .map(|i| {
+                    let commands = &commands;
+                    scope.spawn(move || {
+                        commands
+                            .iter()
+                            .enumerate()
+                            .skip(i)
+                            .step_by(jobs)
+                            .map(|(j, command)| (j, command.preprocess(preprocessor)))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        results.sort_by_key(|(i, _)| *i);
+        let mut files = vec![];
+        let mut diagnostics = vec![];
+        for (_, result) in results {
+            match result {
+                Ok(file) => files.push(file),
+                Err(diagnostic) => diagnostics.push(diagnostic),
+            }
+        }
+        Self::new(files, diagnostics, filter)
     }
 
-    fn new(files: Vec<Preprocessed>) -> Self {
+    fn new(
+        files: Vec<Preprocessed>,
+        mut diagnostics: Vec<Diagnostic>,
+        filter: &LibraryFilter,
+    ) -> Self {
         let mut parses = BTreeMap::new();
+        let mut long_parses = vec![];
         let mut typedef_set = BTreeSet::new();
         let mut struct_set = BTreeSet::new();
         let mut variable_set = BTreeSet::new();
         let mut proto_set = BTreeSet::new();
+        let mut extern_set = BTreeSet::new();
         let mut function_set = BTreeSet::new();
+        let mut headers = BTreeMap::new();
+        let mut macro_defs: BTreeMap<String, Option<Macro>> = BTreeMap::new();
+        let mut defines = BTreeMap::new();
+        let mut directories = BTreeMap::new();
+        let mut function_locations = BTreeMap::new();
+        let mut sources = BTreeMap::new();
+        let mut type_comments = BTreeMap::new();
+        let mut term_comments = BTreeMap::new();
+        let mut type_locations = BTreeMap::new();
+        let mut term_locations = BTreeMap::new();
+        let mut file_comments = BTreeMap::new();
+        let mut function_pointer_typedefs = BTreeSet::new();
+        let mut variadic_pointer_typedefs = BTreeSet::new();
+        let mut variadic_function_pointers = BTreeSet::new();
+        let mut function_pointer_declarations: BTreeMap<_, BTreeSet<_>> = BTreeMap::new();
+        let mut function_pointer_fields: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        let mut enumerators = BTreeMap::new();
 
         let config = Config::with_gcc();
 
         for file in files {
             let path = file.path.to_str().unwrap();
 
-            let parse = driver::parse_preprocessed(&config, file.code)
-                .map_err(|err| format!("{}:{}:{}", path, err.line, err.column))
-                .unwrap();
+            if let Some(comment) = read_source(&mut sources, &file.path).and_then(header_comment) {
+                file_comments.insert(path.to_string(), comment);
+            }
+
+            let (long_code, file_defines) = extract_defines(&file.long_code);
+            // The full output is parsed first because its line markers locate errors.
+            let parsed = driver::parse_preprocessed(&config, long_code).and_then(|long_parse| {
+                Ok((driver::parse_preprocessed(&config, file.code)?, long_parse))
+            });
+            let (parse, long_parse) = match parsed {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    diagnostics.push(Diagnostic {
+                        path: path.to_string(),
+                        message: err.to_string(),
+                        unresolved: vec![],
+                    });
+                    continue;
+                }
+            };
+
+            for (origin, line, define) in file_defines {
+                let mut m = some_or!(Macro::parse(define, path), continue);
+                if !defines.contains_key(&m.name) {
+                    defines.insert(m.name.clone(), m.clone());
+                }
+                if origin.starts_with('<') || filter.is_library(origin) {
+                    continue;
+                }
+                let origin = file.directory.join(origin);
+                let source = read_source(&mut sources, &origin);
+                let lines = source.map(|s| s.lines().skip(line.saturating_sub(1)));
+                m.location = Some(Location {
+                    column: lines
+                        .clone()
+                        .and_then(|mut ls| Some(original_column(ls.next()?, "#", 1)))
+                        .unwrap_or(1),
+                    end_line: lines.map_or(line, |ls| {
+                        line + ls.take_while(|l| l.trim_end().ends_with('\\')).count()
+                    }),
+                    file: origin,
+                    line,
+                });
+                match macro_defs.entry(m.name.clone()) {
+                    Entry::Vacant(e) => {
+                        e.insert(Some(m));
+                    }
+                    Entry::Occupied(mut e) => {
+                        let same = |d: &Macro| d.params == m.params && d.body == m.body;
+                        if !e.get().as_ref().map_or(false, same) {
+                            e.insert(None);
+                        }
+                    }
+                }
+            }
+            directories.insert(path.to_string(), file.directory);
+
             parses.insert(path.to_string(), parse);
 
-            let parse = driver::parse_preprocessed(&config, file.long_code).expect(path);
-            let lib_spans = find_lib_spans(&parse);
+            let lib_spans = find_lib_spans(&long_parse, filter, &mut headers);
+            long_parses.push((path.to_string(), long_parse, lib_spans));
+        }
+
+        let local_names = find_local_names(&long_parses);
+        let mut visitor = TypeNameVisitor::default();
+        for (_, parse, _) in &long_parses {
+            visitor.visit_translation_unit(&parse.unit);
+        }
+        let declared_type_names = visitor.0;
+
+        for (path, parse, lib_spans) in &long_parses {
             let is_lib = |span: Span| lib_spans.iter().any(|s| overlap(*s, span));
+            let term_name = |name: &str| {
+                local_names
+                    .get(path)
+                    .and_then(|names| names.get(name))
+                    .map_or(name, |s| s.as_str())
+                    .to_string()
+            };
+            let markers = line_markers(&parse.source);
 
             for decl in &parse.unit.0 {
+                if let ExternalDeclaration::Declaration(decl) = &decl.node {
+                    if is_typedef(&decl.node) {
+                        for d in &decl.node.declarators {
+                            let declarator = &d.node.declarator.node;
+                            if is_function_pointer(declarator) {
+                                let name = declarator_name(declarator).to_string();
+                                if is_variadic_function_pointer(declarator) {
+                                    variadic_pointer_typedefs.insert(name.clone());
+                                }
+                                function_pointer_typedefs.insert(name);
+                            }
+                        }
+                    }
+                }
                 if is_lib(decl.span) {
                     continue;
                 }
+                let location = line_range(&markers, &parse.source, decl.span)
+                    .map(|(file, start, end)| (directories[path].join(file), start, end));
+                let comment = location.as_ref().and_then(|(file, start, _)| {
+                    leading_comment(read_source(&mut sources, file)?, *start)
+                });
+                let position = location.as_ref().map(|(file, line, end_line)| {
+                    let start = &parse.source[decl.span.start..];
+                    let token = &start[..start
+                        .find(|c: char| !is_identifier_char(c))
+                        .unwrap_or(start.len())];
+                    let column = column(&parse.source, decl.span.start);
+                    let column = read_source(&mut sources, file)
+                        .and_then(|s| s.lines().nth(line.checked_sub(1)?))
+                        .map_or(column, |l| original_column(l, token, column));
+                    Location {
+                        file: file.clone(),
+                        line: *line,
+                        column,
+                        end_line: *end_line,
+                    }
+                });
                 match &decl.node {
                     ExternalDeclaration::Declaration(decl) => {
                         if is_typedef(&decl.node) {
                             for n in declarator_names(&decl.node) {
                                 typedef_set.insert(n.to_string());
+                                let key = (TypeSort::Typedef, n.to_string());
+                                insert_first(&mut type_locations, key.clone(), &position);
+                                insert_first(&mut type_comments, key, &comment);
                             }
                         } else {
                             let extrn = is_extern(&decl.node);
+                            let specifiers = specifier_types(&decl.node.specifiers);
+                            let decl_span = decl.span;
                             for decl in &decl.node.declarators {
-                                let name = declarator_name(&decl.node.declarator.node);
+                                let name = term_name(declarator_name(&decl.node.declarator.node));
                                 if is_function_proto(&decl.node) {
-                                    proto_set.insert(name.to_string());
-                                } else if !extrn {
-                                    variable_set.insert(name.to_string());
+                                    insert_first(&mut term_locations, name.clone(), &position);
+                                    proto_set.insert(name);
+                                } else {
+                                    let declarator = &decl.node.declarator;
+                                    if is_function_pointer(&declarator.node)
+                                        || has_typedef(
+                                            specifiers.iter().copied(),
+                                            &function_pointer_typedefs,
+                                        )
+                                    {
+                                        if is_variadic_function_pointer(&declarator.node)
+                                            || has_typedef(
+                                                specifiers.iter().copied(),
+                                                &variadic_pointer_typedefs,
+                                            )
+                                        {
+                                            variadic_function_pointers.insert(name.clone());
+                                        }
+                                        let span = Span::span(decl_span.start, declarator.span.end);
+                                        let declaration = parse.source[span.start..span.end].into();
+                                        function_pointer_declarations
+                                            .entry(name.clone())
+                                            .or_default()
+                                            .insert(declaration);
+                                    }
+                                    if !extrn {
+                                        insert_first(&mut term_locations, name.clone(), &position);
+                                        insert_first(&mut term_comments, name.clone(), &comment);
+                                        variable_set.insert(name);
+                                    } else {
+                                        extern_set.insert(name);
+                                    }
                                 }
                             }
                         }
-                        for s in &decl.node.specifiers {
-                            if let DeclarationSpecifier::TypeSpecifier(t) = &s.node {
-                                match &t.node {
-                                    TypeSpecifier::Struct(s) => {
-                                        if let Some(id) = &s.node.identifier {
-                                            if s.node.declarations.is_some() {
-                                                struct_set.insert(id.node.name.to_string());
-                                            }
-                                        }
+                        let typedef_names = if is_typedef(&decl.node) {
+                            declarator_names(&decl.node)
+                        } else {
+                            vec![]
+                        };
+                        for def in type_definitions(&decl.node, &declared_type_names) {
+                            let fields = match &def.specifier.node {
+                                TypeSpecifier::Struct(s) => {
+                                    some_or!(s.node.declarations.as_ref(), continue)
+                                }
+                                _ => continue,
+                            };
+                            let mut declarations = vec![];
+                            for field in fields {
+                                let f = if let StructDeclaration::Field(f) = &field.node {
+                                    f
+                                } else {
+                                    continue;
+                                };
+                                let specifiers =
+                                    f.node.specifiers.iter().filter_map(|s| match &s.node {
+                                        SpecifierQualifier::TypeSpecifier(t) => Some(&t.node),
+                                        _ => None,
+                                    });
+                                let typedef =
+                                    has_typedef(specifiers.clone(), &function_pointer_typedefs);
+                                let variadic = has_typedef(specifiers, &variadic_pointer_typedefs);
+                                let text = parse.source[field.span.start..field.span.end].trim();
+                                for d in &f.node.declarators {
+                                    let d = some_or!(&d.node.declarator, continue);
+                                    if variadic || is_variadic_function_pointer(&d.node) {
+                                        variadic_function_pointers
+                                            .insert(declarator_name(&d.node).to_string());
                                     }
-                                    TypeSpecifier::Enum(e) => {
-                                        if let Some(id) = &e.node.identifier {
-                                            if !e.node.enumerators.is_empty() {
-                                                struct_set.insert(id.node.name.to_string());
-                                            }
-                                        }
+                                    if typedef || is_function_pointer(&d.node) {
+                                        function_pointer_declarations
+                                            .entry(declarator_name(&d.node).to_string())
+                                            .or_default()
+                                            .insert(text.to_string());
+                                        declarations.push(text.to_string());
                                     }
-                                    _ => (),
                                 }
                             }
+                            declarations.dedup();
+                            if declarations.is_empty() {
+                                continue;
+                            }
+                            let names = def.name.iter().map(|n| n.as_str());
+                            let names = names.chain(if def.nested {
+                                vec![]
+                            } else {
+                                typedef_names.clone()
+                            });
+                            for name in names {
+                                function_pointer_fields
+                                    .entry(name.to_string())
+                                    .or_insert_with(|| declarations.clone());
+                            }
+                        }
+                        for def in type_definitions(&decl.node, &declared_type_names) {
+                            let e = if let TypeSpecifier::Enum(e) = &def.specifier.node {
+                                e
+                            } else {
+                                continue;
+                            };
+                            let owner = match &def.name {
+                                Some(name) => (TypeSort::Enum, name.clone()),
+                                None if !def.nested && typedef_names.len() == 1 => {
+                                    (TypeSort::Typedef, typedef_names[0].to_string())
+                                }
+                                None => continue,
+                            };
+                            for e in &e.node.enumerators {
+                                enumerators
+                                    .entry(e.node.identifier.node.name.clone())
+                                    .or_insert_with(|| owner.clone());
+                            }
+                        }
+                        for def in type_definitions(&decl.node, &declared_type_names) {
+                            let name = some_or!(def.name, continue);
+                            let sort = match &def.specifier.node {
+                                TypeSpecifier::Struct(s) => match s.node.kind.node {
+                                    StructKind::Struct => TypeSort::Struct,
+                                    StructKind::Union => TypeSort::Union,
+                                },
+                                _ => TypeSort::Enum,
+                            };
+                            insert_first(&mut type_locations, (sort, name.clone()), &position);
+                            if !def.nested {
+                                insert_first(&mut type_comments, (sort, name.clone()), &comment);
+                            }
+                            struct_set.insert(name);
                         }
                     }
                     ExternalDeclaration::FunctionDefinition(func) => {
@@ -228,8 +848,16 @@ impl Program {
                             .unwrap()
                             .node
                             .name
-                            .clone();
-                        function_set.insert(name.to_string());
+                            .as_str();
+                        let name = term_name(name);
+                        if let Some(location) = location {
+                            function_locations.entry(name.clone()).or_insert(location);
+                        }
+                        if let Some(position) = position {
+                            term_locations.insert(name.clone(), position);
+                        }
+                        insert_first(&mut term_comments, name.clone(), &comment);
+                        function_set.insert(name);
                     }
                     _ => (),
                 }
@@ -241,6 +869,73 @@ impl Program {
             proto_set.remove(f);
         }
 
+        for diagnostic in &mut diagnostics {
+            let source = read_source(&mut sources, Path::new(&diagnostic.path));
+            let mentioned: BTreeSet<_> = source.into_iter().flat_map(identifiers).collect();
+            diagnostic.unresolved = proto_set
+                .iter()
+                .chain(extern_set.difference(&variable_set))
+                .filter(|name| source.is_none() || mentioned.contains(name.as_str()))
+                .cloned()
+                .collect();
+        }
+
+        let (library_headers, project_headers): (BTreeMap<_, _>, BTreeMap<_, _>) =
+            headers.into_iter().partition(|(_, is_lib)| *is_lib);
+        let library_headers = library_headers.into_keys().collect();
+        let project_headers = project_headers.into_keys().collect();
+
+        let mut macros = BTreeMap::new();
+        let mut skipped_macros = BTreeMap::new();
+        for (name, m) in macro_defs {
+            let reason = match &m {
+                None => Some("defined differently in several places"),
+                Some(_)
+                    if function_set.contains(&name)
+                        || variable_set.contains(&name)
+                        || proto_set.contains(&name) =>
+                {
+                    Some("named like a function or a variable")
+                }
+                Some(m) => m.skip_reason(&declared_type_names),
+            };
+            match reason {
+                Some(reason) => {
+                    skipped_macros.insert(name, reason.to_string());
+                }
+                None => {
+                    macros.insert(name, m.unwrap());
+                }
+            }
+        }
+        // Macros are translated before functions and only refer to translatable macros.
+        loop {
+            let skipped: Vec<_> = macros
+                .values()
+                .filter_map(|m: &Macro| {
+                    let reason = m.identifiers().into_iter().find_map(|(tag, id)| {
+                        if tag.is_some() {
+                            None
+                        } else if skipped_macros.contains_key(id) {
+                            Some(format!("uses the skipped macro `{}`", id))
+                        } else if function_set.contains(id) {
+                            Some(format!("uses the function `{}`", id))
+                        } else {
+                            None
+                        }
+                    })?;
+                    Some((m.name.clone(), reason))
+                })
+                .collect();
+            if skipped.is_empty() {
+                break;
+            }
+            for (name, reason) in skipped {
+                macros.remove(&name);
+                skipped_macros.insert(name, reason);
+            }
+        }
+
         Self {
             parses,
             typedef_set,
@@ -248,9 +943,62 @@ impl Program {
             variable_set,
             proto_set,
             function_set,
+            local_names,
+            library_headers,
+            project_headers,
+            macros,
+            skipped_macros,
+            defines,
+            function_locations,
+            type_comments,
+            term_comments,
+            type_locations,
+            term_locations,
+            file_comments,
+            diagnostics,
+            function_pointer_declarations,
+            variadic_function_pointers,
+            variadic_pointer_typedefs,
+            function_pointer_fields,
+            enumerators,
+            declared_type_names,
         }
     }
 
+    /// Returns the name identifying the file-scope symbol `name` referred to in `path`.
+    ///
+    /// `static` symbols defined in multiple files are distinguished by the files.
+    pub fn term_name<'a>(&'a self, path: &str, name: &'a str) -> &'a str {
+        self.local_names
+            .get(path)
+            .and_then(|names| names.get(name))
+            .map_or(name, |s| s.as_str())
+    }
+
+    pub fn type_comment(&self, ty: &CustomType<'_>) -> Option<&str> {
+        self.type_comments
+            .get(&(ty.sort, ty.name.to_string()))
+            .map(|s| s.as_str())
+    }
+
+    pub fn term_comment(&self, name: &str) -> Option<&str> {
+        self.term_comments.get(name).map(|s| s.as_str())
+    }
+
+    pub fn type_location(&self, ty: &CustomType<'_>) -> Option<&Location> {
+        self.type_locations.get(&(ty.sort, ty.name.to_string()))
+    }
+
+    pub fn term_location(&self, name: &str) -> Option<&Location> {
+        self.term_locations
+            .get(name)
+            .or_else(|| self.macros.get(name)?.location.as_ref())
+    }
+
+    pub fn file_comment(&self, path: &str) -> Option<&str> {
+        self.file_comments.get(path).map(|s| s.as_str())
+    }
+
     pub fn lines(&self, path: &str, span: Span) -> usize {
         self.parses.get(path).unwrap().source[span.start..span.end]
             .lines()
@@ -277,7 +1025,7 @@ impl Program {
                         }
                         let identifier = get_identifier(&declarator.node).unwrap();
                         let types = type_specifiers(&decl.node);
-                        let mut visitor = TypeSpecifierVisitor::default();
+                        let mut visitor = TypeSpecifierVisitor::new(self.nested_types(&decl.node));
                         let mut struct_or_enum = None;
                         for t in &types {
                             let span = t.span;
@@ -294,7 +1042,11 @@ impl Program {
                                         } else {
                                             CustomType::mk_union(x)
                                         };
-                                        let t = TypeDependency { typ, span };
+                                        let t = TypeDependency {
+                                            typ,
+                                            span,
+                                            enumerator: None,
+                                        };
                                         struct_or_enum = Some(t);
                                         break;
                                     }
@@ -306,7 +1058,11 @@ impl Program {
                                             continue;
                                         }
                                         let typ = CustomType::mk_enum(x);
-                                        let t = TypeDependency { typ, span };
+                                        let t = TypeDependency {
+                                            typ,
+                                            span,
+                                            enumerator: None,
+                                        };
                                         struct_or_enum = Some(t);
                                         break;
                                     }
@@ -419,43 +1175,39 @@ impl Program {
             let path = path.as_str();
             for decl in &parse.unit.0 {
                 if let ExternalDeclaration::Declaration(decl) = &decl.node {
-                    for s in &decl.node.specifiers {
-                        if let DeclarationSpecifier::TypeSpecifier(t) = &s.node {
-                            if let TypeSpecifier::Struct(s) = &t.node {
-                                let name = if let Some(id) = &s.node.identifier {
-                                    id.node.name.as_str()
-                                } else {
-                                    continue;
-                                };
-                                let declarations = if let Some(declarations) = &s.node.declarations
-                                {
-                                    declarations
-                                } else {
-                                    continue;
-                                };
-                                if !struct_set.remove(name) {
-                                    continue;
-                                }
-                                let mut visitor = TypeSpecifierVisitor::default();
-                                for d in declarations {
-                                    if let StructDeclaration::Field(f) = &d.node {
-                                        visitor.visit_struct_field(&f.node, &f.span);
-                                    }
-                                }
-                                let mut dependencies = visitor.0;
-                                self.refine_type_dependencies(&mut dependencies);
-                                let strct = matches!(s.node.kind.node, StructKind::Struct);
-                                let s = Struct {
-                                    declaration: decl,
-                                    strct,
-                                    name,
-                                    struct_type: s,
-                                    dependencies,
-                                    path,
-                                };
-                                structs.insert(name, s);
+                    for def in type_definitions(&decl.node, &self.declared_type_names) {
+                        let s = if let TypeSpecifier::Struct(s) = &def.specifier.node {
+                            s
+                        } else {
+                            continue;
+                        };
+                        let name = some_or!(def.name, continue);
+                        let name = some_or!(struct_set.take(name.as_str()), continue);
+                        let declarations = s.node.declarations.as_ref().unwrap();
+                        let mut visitor = TypeSpecifierVisitor::new(self.nested_types(&decl.node));
+                        for d in declarations {
+                            if let StructDeclaration::Field(f) = &d.node {
+                                visitor.visit_struct_field(&f.node, &f.span);
                             }
                         }
+                        let mut dependencies = visitor.0;
+                        self.refine_type_dependencies(&mut dependencies);
+                        let strct = matches!(s.node.kind.node, StructKind::Struct);
+                        let bit_fields = if strct {
+                            bit_fields(declarations)
+                        } else {
+                            None
+                        };
+                        let s = Struct {
+                            declaration: decl,
+                            strct,
+                            name,
+                            struct_type: s,
+                            dependencies,
+                            bit_fields,
+                            path,
+                        };
+                        structs.insert(name, s);
                     }
                 }
             }
@@ -480,26 +1232,21 @@ impl Program {
             let path = path.as_str();
             for decl in &parse.unit.0 {
                 if let ExternalDeclaration::Declaration(decl) = &decl.node {
-                    for s in &decl.node.specifiers {
-                        if let DeclarationSpecifier::TypeSpecifier(t) = &s.node {
-                            if let TypeSpecifier::Enum(e) = &t.node {
-                                let name = if let Some(id) = &e.node.identifier {
-                                    id.node.name.as_str()
-                                } else {
-                                    continue;
-                                };
-                                if !enum_set.remove(name) {
-                                    continue;
-                                }
-                                let e = Enum {
-                                    declaration: decl,
-                                    name,
-                                    enum_type: e,
-                                    path,
-                                };
-                                enums.insert(name, e);
-                            }
-                        }
+                    for def in type_definitions(&decl.node, &self.declared_type_names) {
+                        let e = if let TypeSpecifier::Enum(e) = &def.specifier.node {
+                            e
+                        } else {
+                            continue;
+                        };
+                        let name = some_or!(def.name, continue);
+                        let name = some_or!(enum_set.take(name.as_str()), continue);
+                        let e = Enum {
+                            declaration: decl,
+                            name,
+                            enum_type: e,
+                            path,
+                        };
+                        enums.insert(name, e);
                     }
                 }
             }
@@ -526,24 +1273,27 @@ impl Program {
                     let cnst = is_const(&decl.node);
                     for declarator in &decl.node.declarators {
                         let d = &declarator.node.declarator;
-                        let name = declarator_name(&d.node);
+                        let name = self.term_name(path, declarator_name(&d.node));
                         let identifier = get_identifier(&d.node).unwrap();
                         let types = type_specifiers(&decl.node);
-                        let mut visitor = TypeSpecifierVisitor::default();
+                        let mut visitor = TypeSpecifierVisitor::new(self.nested_types(&decl.node));
                         for s in &decl.node.specifiers {
                             visitor.visit_declaration_specifier(&s.node, &s.span);
                         }
                         visitor.visit_init_declarator(&declarator.node, &declarator.span);
                         let mut type_dependencies = visitor.0;
-                        self.refine_type_dependencies(&mut type_dependencies);
-                        let mut dependencies = if let Some(i) = &declarator.node.initializer {
+                        let dependencies = if let Some(i) = &declarator.node.initializer {
                             let mut visitor = IdentifierVisitor::default();
                             visitor.visit_initializer(&i.node, &i.span);
                             visitor.0
                         } else {
                             vec![]
                         };
-                        self.refine_dependencies(&mut dependencies);
+                        type_dependencies.extend(self.enumerator_dependencies(&dependencies));
+                        self.refine_type_dependencies(&mut type_dependencies);
+                        let function_pointers =
+                            self.stored_functions(path, decl, declarator, dependencies.clone());
+                        let dependencies = self.refine_dependencies(path, dependencies);
                         let variable = Variable {
                             declaration: decl,
                             cnst,
@@ -553,6 +1303,7 @@ impl Program {
                             declarator,
                             type_dependencies,
                             dependencies,
+                            function_pointers,
                             path,
                         };
                         if is_function_proto(&declarator.node) {
@@ -628,23 +1379,31 @@ impl Program {
     pub fn functions(&self) -> BTreeMap<&str, Function<'_>> {
         let mut functions = BTreeMap::new();
         let mut function_set: BTreeSet<_> = self.function_set.iter().map(|s| s.as_str()).collect();
+        let mut sources = BTreeMap::new();
 
         for (path, parse) in &self.parses {
             let path = path.as_str();
+            let table = TypeTable::new(&parse.unit, &self.typedef_set);
             for decl in &parse.unit.0 {
                 if let ExternalDeclaration::FunctionDefinition(func) = &decl.node {
                     let identifier = get_identifier(&func.node.declarator.node).unwrap();
-                    let name = identifier.node.name.as_str();
+                    let name = self.term_name(path, &identifier.node.name);
                     if !function_set.remove(name) {
                         continue;
                     }
 
+                    let variadic = is_variadic(&func.node.declarator.node);
                     let params = match function_derived(&func.node.declarator.node) {
                         DerivedDeclarator::Function(d) => d
                             .node
                             .parameters
                             .iter()
-                            .map(|p| type_of(&p.node.specifiers, p.node.declarator.as_ref()))
+                            .map(|p| {
+                                table.declaration_type(
+                                    &p.node.specifiers,
+                                    p.node.declarator.as_ref(),
+                                )
+                            })
                             .collect::<Vec<_>>(),
                         DerivedDeclarator::KRFunction(ps) => {
                             let mut decls: BTreeMap<_, _> = func
@@ -658,14 +1417,21 @@ impl Program {
                                             .node
                                             .name
                                             .as_str();
-                                        let ty =
-                                            type_of(&d.node.specifiers, Some(&i.node.declarator));
+                                        let ty = table.declaration_type(
+                                            &d.node.specifiers,
+                                            Some(&i.node.declarator),
+                                        );
                                         (x, ty)
                                     })
                                 })
                                 .collect();
+                            // Undeclared parameters are implicitly `int`.
                             ps.iter()
-                                .map(|x| decls.remove(x.node.name.as_str()).unwrap())
+                                .map(|x| {
+                                    decls
+                                        .remove(x.node.name.as_str())
+                                        .unwrap_or_else(|| Type::from_name("int".to_string()))
+                                })
                                 .collect()
                         }
                         _ => panic!(
@@ -678,26 +1444,37 @@ impl Program {
                     } else {
                         params
                     };
-                    let ret = type_of(&func.node.specifiers, Some(&func.node.declarator));
+                    let ret =
+                        table.declaration_type(&func.node.specifiers, Some(&func.node.declarator));
                     let type_signature = FunTySig {
                         params,
                         ret,
                         generic: false,
+                        variadic,
                     };
 
+                    let mut dependencies = get_identifiers(&func.node);
+                    let mut local: BTreeSet<_> =
+                        get_local_variables(&func.node).into_iter().collect();
+                    local.extend(parameter_names(&func.node.declarator.node));
+                    dependencies.retain(|i| !local.contains(i.node.name.as_str()));
+
                     let mut visitor = TypeSpecifierVisitor::default();
                     visitor.visit_function_definition(&func.node, &func.span);
                     let mut type_dependencies = visitor.0;
+                    type_dependencies.extend(self.enumerator_dependencies(&dependencies));
                     self.refine_type_dependencies(&mut type_dependencies);
 
-                    let mut dependencies = get_identifiers(&func.node);
-                    let local: BTreeSet<_> = get_local_variables(&func.node).into_iter().collect();
-                    dependencies.retain(|i| !local.contains(i.node.name.as_str()));
-                    self.refine_dependencies(&mut dependencies);
+                    let dependencies = self.refine_dependencies(path, dependencies);
 
-                    let mut callees = get_callees(&func.node);
+                    let callees = get_callees(&func.node);
                     let calls = callees.len();
-                    self.refine_callees(&mut callees);
+                    let callees = self.refine_callees(path, callees);
+
+                    let (function_pointers, indirect_calls, variadic_indirect_calls) =
+                        self.function_pointer_uses(path, name, &func.node, &local);
+
+                    let macros = self.macro_uses(path, name, func.span, &mut sources);
 
                     let f = Function {
                         identifier,
@@ -708,6 +1485,10 @@ impl Program {
                         dependencies,
                         callees,
                         calls,
+                        macros,
+                        function_pointers,
+                        indirect_calls,
+                        variadic_indirect_calls,
                         path,
                     };
                     functions.insert(name, f);
@@ -719,26 +1500,204 @@ impl Program {
         functions
     }
 
-    pub fn function_to_string(
-        &self,
-        function: &Function<'_>,
-        mut vec: Vec<(Span, &str)>,
-    ) -> String {
-        for s in &function.definition.node.specifiers {
-            if matches!(
-                s.node,
-                DeclarationSpecifier::StorageClass(_)
-                    | DeclarationSpecifier::Function(_)
-                    | DeclarationSpecifier::Alignment(_)
-                    | DeclarationSpecifier::Extension(_)
-            ) {
-                vec.push((s.span, ""));
+    /// Finds the functions used as values in a function and the numbers of arguments of its calls
+    /// through function pointers.
+    fn function_pointer_uses<'a>(
+        &'a self,
+        path: &str,
+        name: &str,
+        func: &'a FunctionDefinition,
+        local: &BTreeSet<&str>,
+    ) -> (Vec<FunctionPointerUse<'a>>, Vec<usize>, Vec<&'a str>) {
+        let mut visitor = FunctionPointerVisitor::default();
+        visitor.visit_statement(&func.statement.node, &func.statement.span);
+
+        let function_pointers = visitor
+            .uses
+            .into_iter()
+            .filter(|(x, _)| !local.contains(x.node.name.as_str()))
+            .filter_map(|(x, u)| {
+                let function = TermDependency {
+                    name: self.term_name(path, &x.node.name),
+                    span: x.span,
+                };
+                if !self.is_function(function.name) {
+                    return None;
+                }
+                let context = match u {
+                    PointerUse::Argument(callee, i) => format!(
+                        "passed as argument {} of `{}` in `{}`",
+                        i + 1,
+                        self.span_to_string(path, callee.span),
+                        name
+                    ),
+                    PointerUse::Assignment(lhs) => {
+                        let target = self.span_to_string(path, lhs.span);
+                        let declarations = assigned_name(&lhs.node)
+                            .map(|(n, field)| if field { n } else { self.term_name(path, n) })
+                            .and_then(|n| self.function_pointer_declarations.get(n));
+                        match declarations {
+                            Some(ds) => format!(
+                                "assigned to `{}` in `{}`, declared as `{}`",
+                                target,
+                                name,
+                                ds.iter()
+                                    .map(|d| d.as_str())
+                                    .collect::<Vec<_>>()
+                                    .join("` or `")
+                            ),
+                            None => format!("assigned to `{}` in `{}`", target, name),
+                        }
+                    }
+                    PointerUse::Value => format!("used as a value in `{}`", name),
+                };
+                Some(FunctionPointerUse { function, context })
+            })
+            .collect();
+
+        let variadic_params: BTreeSet<_> = match function_derived(&func.declarator.node) {
+            DerivedDeclarator::Function(d) => d
+                .node
+                .parameters
+                .iter()
+                .filter_map(|p| {
+                    let decl = &p.node.declarator.as_ref()?.node;
+                    let specifiers = specifier_types(&p.node.specifiers);
+                    let variadic = is_variadic_function_pointer(decl)
+                        || has_typedef(specifiers, &self.variadic_pointer_typedefs);
+                    variadic.then(|| declarator_name(decl))
+                })
+                .collect(),
+            _ => BTreeSet::new(),
+        };
+        let mut indirect_calls = vec![];
+        let mut variadic_indirect_calls = vec![];
+        for (callee, n) in visitor.calls {
+            let variadic = match &callee.node {
+                Expression::Identifier(x) => {
+                    let x = x.node.name.as_str();
+                    if local.contains(x) {
+                        variadic_params.contains(x)
+                    } else {
+                        let x = self.term_name(path, x);
+                        if !self.variable_set.contains(x)
+                            && !self.function_pointer_declarations.contains_key(x)
+                        {
+                            continue;
+                        }
+                        self.variadic_function_pointers.contains(x)
+                    }
+                }
+                Expression::Member(m) => self
+                    .variadic_function_pointers
+                    .contains(&m.node.identifier.node.name),
+                _ => false,
+            };
+            indirect_calls.push(n);
+            if variadic {
+                variadic_indirect_calls.push(self.span_to_string(path, callee.span));
             }
         }
-        self.replace(function.definition, function.path, vec)
+
+        (function_pointers, indirect_calls, variadic_indirect_calls)
     }
 
-    pub fn function_to_signature_string<S: AsRef<str> + Clone>(
+    /// Finds the functions stored by the initializer of a variable.
+    fn stored_functions<'a>(
+        &'a self,
+        path: &str,
+        decl: &'a Node<Declaration>,
+        declarator: &'a Node<InitDeclarator>,
+        identifiers: Vec<&'a Node<Identifier>>,
+    ) -> Vec<FunctionPointerUse<'a>> {
+        let span = Span::span(decl.span.start, declarator.node.declarator.span.end);
+        let declaration = self.span_to_string(path, span).trim_end();
+        let mut types: Vec<_> = type_definitions(&decl.node, &self.declared_type_names)
+            .into_iter()
+            .filter_map(|def| def.name)
+            .collect();
+        for t in type_specifiers(&decl.node) {
+            match &t.node {
+                TypeSpecifier::Struct(s) => {
+                    types.extend(s.node.identifier.as_ref().map(|x| x.node.name.to_string()))
+                }
+                TypeSpecifier::TypedefName(x) => types.push(x.node.name.to_string()),
+                _ => (),
+            }
+        }
+        let fields: Vec<_> = types
+            .iter()
+            .filter_map(|t| self.function_pointer_fields.get(t))
+            .flatten()
+            .map(|f| f.as_str())
+            .collect();
+        let context = if fields.is_empty() {
+            format!("stored in `{}`", declaration)
+        } else {
+            format!(
+                "stored in `{}`, whose function-pointer fields are declared as `{}`",
+                declaration,
+                fields.join("`, `")
+            )
+        };
+        identifiers
+            .into_iter()
+            .map(|x| TermDependency {
+                name: self.term_name(path, &x.node.name),
+                span: x.span,
+            })
+            .filter(|d| self.is_function(d.name))
+            .map(|function| FunctionPointerUse {
+                function,
+                context: context.clone(),
+            })
+            .collect()
+    }
+
+    fn is_function(&self, name: &str) -> bool {
+        self.proto_set.contains(name) || self.function_set.contains(name)
+    }
+
+    /// Finds the uses of the recovered macros in a function by comparing its preprocessed code
+    /// with its original source.
+    fn macro_uses(
+        &self,
+        path: &str,
+        name: &str,
+        span: Span,
+        sources: &mut BTreeMap<PathBuf, Option<String>>,
+    ) -> Vec<MacroUse<'_>> {
+        let (file, start, end) = some_or!(self.function_locations.get(name), return vec![]);
+        let source = some_or!(read_source(sources, file), return vec![]);
+        let original: Vec<_> = source
+            .lines()
+            .skip(start.saturating_sub(1))
+            .take(end + 1 - start)
+            .collect();
+        let code = &self.parses[path].source[span.start..span.end];
+        macro_spans(&original, code, span.start, &self.macros, &self.defines)
+    }
+
+    pub fn function_to_string(
+        &self,
+        function: &Function<'_>,
+        mut vec: Vec<(Span, &str)>,
+    ) -> String {
+        for s in &function.definition.node.specifiers {
+            if matches!(
+                s.node,
+                DeclarationSpecifier::StorageClass(_)
+                    | DeclarationSpecifier::Function(_)
+                    | DeclarationSpecifier::Alignment(_)
+                    | DeclarationSpecifier::Extension(_)
+            ) {
+                vec.push((s.span, ""));
+            }
+        }
+        self.replace(function.definition, function.path, vec)
+    }
+
+    pub fn function_to_signature_string<S: AsRef<str> + Clone>(
         &self,
         function: &Function<'_>,
         vec: Vec<(Span, S)>,
@@ -791,6 +1750,36 @@ impl Program {
         res
     }
 
+    /// Maps the spans of the nested and anonymous type definitions in `decl` to their names.
+    fn nested_types(&self, decl: &Declaration) -> BTreeMap<usize, &str> {
+        type_definitions(decl, &self.declared_type_names)
+            .into_iter()
+            .filter(|def| def.nested || def.anonymous)
+            .filter_map(|def| {
+                let name = self.struct_set.get(def.name?.as_str())?;
+                Some((def.specifier.span.start, name.as_str()))
+            })
+            .collect()
+    }
+
+    /// Records the uses of enumerators as dependencies on the types defining them.
+    fn enumerator_dependencies<'a>(
+        &'a self,
+        identifiers: &[&'a Node<Identifier>],
+    ) -> Vec<TypeDependency<'a>> {
+        identifiers
+            .iter()
+            .filter_map(|x| {
+                let (sort, name) = self.enumerators.get(&x.node.name)?;
+                Some(TypeDependency {
+                    typ: CustomType { name, sort: *sort },
+                    span: x.span,
+                    enumerator: Some(&x.node.name),
+                })
+            })
+            .collect()
+    }
+
     pub fn refine_type_dependencies(&self, deps: &mut Vec<TypeDependency<'_>>) {
         deps.retain(|d| match d.typ.sort {
             TypeSort::Typedef => self.typedef_set.contains(d.typ.name),
@@ -798,14 +1787,32 @@ impl Program {
         });
     }
 
-    pub fn refine_dependencies(&self, deps: &mut Vec<&Node<Identifier>>) {
-        deps.retain(|d| self.variable_set.contains(&d.node.name));
+    pub fn refine_dependencies<'a>(
+        &'a self,
+        path: &str,
+        deps: Vec<&'a Node<Identifier>>,
+    ) -> Vec<TermDependency<'a>> {
+        deps.into_iter()
+            .map(|d| TermDependency {
+                name: self.term_name(path, &d.node.name),
+                span: d.span,
+            })
+            .filter(|d| self.variable_set.contains(d.name))
+            .collect()
     }
 
-    pub fn refine_callees(&self, deps: &mut Vec<&Node<Identifier>>) {
-        deps.retain(|d| {
-            self.proto_set.contains(&d.node.name) || self.function_set.contains(&d.node.name)
-        });
+    pub fn refine_callees<'a>(
+        &'a self,
+        path: &str,
+        deps: Vec<&'a Node<Identifier>>,
+    ) -> Vec<TermDependency<'a>> {
+        deps.into_iter()
+            .map(|d| TermDependency {
+                name: self.term_name(path, &d.node.name),
+                span: d.span,
+            })
+            .filter(|d| self.is_function(d.name))
+            .collect()
     }
 }
 
@@ -837,28 +1844,655 @@ fn type_specifiers(decl: &Declaration) -> Vec<&Node<TypeSpecifier>> {
         .collect()
 }
 
-fn find_lib_spans(parse: &Parse) -> Vec<Span> {
-    let mut lib_start = None;
-    let mut lib_spans = vec![];
+/// Decides which files are system libraries, whose declarations are not translated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LibraryFilter {
+    /// Patterns of library paths (`*` matches any sequence of characters).
+    pub paths: Vec<String>,
+    /// Patterns of paths in `paths` that still belong to the project.
+    pub exclude: Vec<String>,
+}
+
+impl Default for LibraryFilter {
+    fn default() -> Self {
+        Self {
+            paths: vec!["/usr/*".to_string()],
+            exclude: vec!["/usr/include/arpa/*".to_string()],
+        }
+    }
+}
+
+impl LibraryFilter {
+    pub fn is_library(&self, path: &str) -> bool {
+        self.paths.iter().any(|p| glob_match(p, path))
+            && !self.exclude.iter().any(|p| glob_match(p, path))
+    }
+}
+
+fn find_lib_spans(
+    parse: &Parse,
+    filter: &LibraryFilter,
+    headers: &mut BTreeMap<String, bool>,
+) -> Vec<Span> {
+    let mut lib_spans: Vec<Span> = vec![];
+    for (span, path) in file_spans(parse) {
+        let is_lib = filter.is_library(path);
+        if !path.starts_with('<') {
+            headers.insert(path.to_string(), is_lib);
+        }
+        if !is_lib {
+            continue;
+        }
+        match lib_spans.last_mut() {
+            Some(last) if last.end == span.start => last.end = span.end,
+            _ => lib_spans.push(span),
+        }
+    }
+    lib_spans
+}
+
+/// Splits preprocessed code with line markers into the spans of the files they come from.
+fn file_spans(parse: &Parse) -> Vec<(Span, &str)> {
+    let mut spans = vec![];
+    let mut current: Option<(usize, &str)> = None;
     let mut pos = 0;
     for line in parse.source.lines() {
-        if line.starts_with('#') && !line.starts_with("#pragma") {
-            let path = line
-                .split(' ')
-                .find_map(|s| s.strip_prefix('"'))
-                .expect(line);
-            if path.starts_with("/usr") && !path.starts_with("/usr/include/arpa") {
-                lib_start.get_or_insert(pos);
-            } else if let Some(start) = lib_start.take() {
-                lib_spans.push(Span::span(start, pos));
+        if let Some((_, path)) = line_marker(line) {
+            if let Some((start, path)) = current.take() {
+                spans.push((Span::span(start, pos), path));
             }
+            current = Some((pos, path));
         }
         pos += line.len() + 1;
     }
-    if let Some(start) = lib_start {
-        lib_spans.push(Span::span(start, pos - 1));
+    if let Some((start, path)) = current {
+        spans.push((Span::span(start, pos - 1), path));
     }
-    lib_spans
+    spans
+}
+
+/// Parses a line marker `# <line> "<file>" <flags>...`.
+fn line_marker(line: &str) -> Option<(usize, &str)> {
+    let rest = line.strip_prefix('#')?.trim_start();
+    let (line, rest) = rest.split_once(' ')?;
+    let line = line.parse().ok()?;
+    let path = rest.strip_prefix('"')?;
+    let path = &path[..path.find('"')?];
+    Some((line, path))
+}
+
+/// Returns the line markers of `source` as the position following each marker, the line number,
+/// and the file.
+fn line_markers(source: &str) -> Vec<(usize, usize, &str)> {
+    let mut markers = vec![];
+    let mut pos = 0;
+    for line in source.lines() {
+        pos += line.len() + 1;
+        if let Some((n, path)) = line_marker(line) {
+            markers.push((pos, n, path));
+        }
+    }
+    markers
+}
+
+/// Returns the original file and line numbers of the first and last lines of `span`.
+fn line_range<'a>(
+    markers: &[(usize, usize, &'a str)],
+    source: &str,
+    span: Span,
+) -> Option<(&'a str, usize, usize)> {
+    let location = |pos: usize| {
+        let i = markers.partition_point(|(start, _, _)| *start <= pos);
+        let (start, n, path) = markers[i.checked_sub(1)?];
+        Some((path, n + source[start..pos].matches('\n').count()))
+    };
+    let (path, start) = location(span.start)?;
+    let (end_path, end) = location(span.end.checked_sub(1)?)?;
+    if path != end_path {
+        return None;
+    }
+    Some((path, start, end))
+}
+
+/// Removes the `#define` and `#undef` lines, keeping the line structure, and returns the
+/// definitions with the files and lines they come from.
+fn extract_defines(code: &str) -> (String, Vec<(&str, usize, &str)>) {
+    let mut new_code = String::with_capacity(code.len());
+    let mut defines = vec![];
+    let mut file = "";
+    let mut n = 1;
+    for line in code.lines() {
+        let mut next = n + 1;
+        if let Some(define) = line.strip_prefix("#define ") {
+            defines.push((file, n, define));
+        } else if !line.starts_with("#undef ") {
+            if let Some((m, path)) = line_marker(line) {
+                file = path;
+                next = m;
+            }
+            new_code.push_str(line);
+        }
+        new_code.push('\n');
+        n = next;
+    }
+    (new_code, defines)
+}
+
+/// Reads an original source file, caching the result.
+fn read_source<'a>(
+    sources: &'a mut BTreeMap<PathBuf, Option<String>>,
+    path: &Path,
+) -> Option<&'a str> {
+    sources
+        .entry(path.to_path_buf())
+        .or_insert_with(|| fs::read_to_string(path).ok())
+        .as_deref()
+}
+
+/// Keeps the first value for each key, e.g., of a declaration in a header included by several
+/// translation units.
+fn insert_first<K: Ord, V: Clone>(map: &mut BTreeMap<K, V>, key: K, value: &Option<V>) {
+    if let Some(value) = value {
+        map.entry(key).or_insert_with(|| value.clone());
+    }
+}
+
+/// Returns the 1-based column of `pos` in `source`.
+fn column(source: &str, pos: usize) -> usize {
+    pos - source[..pos].rfind('\n').map_or(0, |i| i + 1) + 1
+}
+
+/// Returns the 1-based column in an original `line` of a declaration that starts with `token` at
+/// `column` in the preprocessed code. The occurrence of `token` closest to `column` is taken, or
+/// the first non-blank character if `token` results from a macro expansion.
+fn original_column(line: &str, token: &str, column: usize) -> usize {
+    let occurrence = line
+        .match_indices(token)
+        .map(|(i, _)| i + 1)
+        .filter(|_| !token.is_empty())
+        .min_by_key(|i| i.abs_diff(column));
+    occurrence.unwrap_or_else(|| line.find(|c: char| !c.is_whitespace()).unwrap_or(0) + 1)
+}
+
+/// Returns the comments immediately preceding line `line` (1-based) of `source`.
+fn leading_comment(source: &str, line: usize) -> Option<String> {
+    let lines: Vec<_> = source.lines().take(line.checked_sub(1)?).collect();
+    let mut i = lines.len();
+    while i > 0 {
+        let l = lines[i - 1].trim();
+        if l.starts_with("//") {
+            i -= 1;
+        } else if l.ends_with("*/") {
+            let start = (0..i).rev().find(|j| lines[*j].contains("/*"))?;
+            if !lines[start].trim().starts_with("/*") {
+                break;
+            }
+            i = start;
+        } else {
+            break;
+        }
+    }
+    clean_comment(&lines[i..])
+}
+
+/// Returns the comments at the beginning of `source` before any blank line or code.
+fn header_comment(source: &str) -> Option<String> {
+    let mut lines = vec![];
+    let mut in_block = false;
+    for line in source.lines() {
+        let l = line.trim();
+        if in_block || l.starts_with("/*") {
+            in_block = !l.contains("*/");
+        } else if l.is_empty() && lines.is_empty() {
+            continue;
+        } else if !l.starts_with("//") {
+            break;
+        }
+        lines.push(line);
+    }
+    clean_comment(&lines)
+}
+
+/// Removes the comment delimiters from lines consisting of comments.
+fn clean_comment(lines: &[&str]) -> Option<String> {
+    let lines: Vec<_> = lines
+        .iter()
+        .map(|line| {
+            let line = line.trim();
+            let line = if let Some(line) = line.strip_prefix("//") {
+                line.trim_start_matches('/')
+            } else {
+                let line = line.trim_start_matches("/*").trim_end_matches("*/");
+                line.trim_start_matches('*')
+            };
+            line.strip_prefix(' ').unwrap_or(line).trim_end()
+        })
+        .collect();
+    let start = lines.iter().position(|l| !l.is_empty())?;
+    let end = lines.iter().rposition(|l| !l.is_empty())?;
+    Some(lines[start..=end].join("\n"))
+}
+
+/// Finds the expansions in `expanded` of the uses of `macros` in `original`. Lines are compared
+/// one by one, and the expansion of a use is located only when its tokens occur in the expanded
+/// line exactly as many times as such uses in the original line, and never in the original line
+/// itself. The arguments of a function-like macro are located by the tokens taken from them.
+fn macro_spans<'a>(
+    original: &[&str],
+    expanded: &str,
+    offset: usize,
+    macros: &'a BTreeMap<String, Macro>,
+    defines: &BTreeMap<String, Macro>,
+) -> Vec<MacroUse<'a>> {
+    let lines: Vec<_> = expanded.split('\n').collect();
+    if lines.len() != original.len() {
+        return vec![];
+    }
+    let mut uses = vec![];
+    let mut pos = offset;
+    for (expanded, original) in lines.into_iter().zip(original) {
+        let original = tokenize(original);
+        let expanded_tokens = tokenize(expanded);
+
+        let mut expansions: BTreeMap<Vec<&str>, Vec<Expansion<'a, '_>>> = BTreeMap::new();
+        let mut i = 0;
+        while i < original.len() {
+            let (name, m) = some_or!(macros.get_key_value(original[i].1), {
+                i += 1;
+                continue;
+            });
+            let mut tokens = vec![(original[i].1, None)];
+            let mut args = vec![];
+            let mut next = i + 1;
+            if m.is_function() {
+                if original.get(i + 1).map(|t| t.1) != Some("(") {
+                    i += 1;
+                    continue;
+                }
+                let rest: Vec<_> = original[i + 1..].iter().map(|t| (t.1, None)).collect();
+                let (mut arguments, close) = some_or!(split_arguments(&rest), break);
+                if arguments.len() == 1 && arguments[0].is_empty() {
+                    arguments.clear();
+                }
+                tokens.push(("(", None));
+                for (j, arg) in arguments.into_iter().enumerate() {
+                    if j > 0 {
+                        tokens.push((",", None));
+                    }
+                    let arg: Vec<_> = arg.into_iter().map(|(t, _)| (t, Some(j))).collect();
+                    args.push(expand(&arg, defines, &mut vec![]).map_or(0, |a| a.len()));
+                    tokens.extend(arg);
+                }
+                tokens.push((")", None));
+                next = i + close + 2;
+            }
+            if let Some(expansion) = expand(&tokens, defines, &mut vec![]) {
+                let key = expansion.iter().map(|t| t.0).collect();
+                expansions
+                    .entry(key)
+                    .or_default()
+                    .push((name, expansion, args));
+            }
+            i = next;
+        }
+
+        let original: Vec<_> = original.into_iter().map(|t| t.1).collect();
+        let tokens: Vec<_> = expanded_tokens.iter().map(|t| t.1).collect();
+        for (key, group) in expansions {
+            if key.is_empty() || !occurrences(&original, &key).is_empty() {
+                continue;
+            }
+            let positions = occurrences(&tokens, &key);
+            if positions.len() != group.len() {
+                continue;
+            }
+            let span = |i: usize, n: usize| {
+                let (start, _) = expanded_tokens[i];
+                let (end, token) = expanded_tokens[i + n - 1];
+                Span::span(pos + start, pos + end + token.len())
+            };
+            for (p, (name, expansion, arg_lens)) in positions.into_iter().zip(group) {
+                let whole = span(p, key.len());
+                let args: Option<Vec<_>> = arg_lens
+                    .iter()
+                    .enumerate()
+                    .map(|(j, len)| {
+                        let start = expansion.iter().position(|t| t.1 == Some(j))?;
+                        let run = expansion[start..]
+                            .iter()
+                            .take_while(|t| t.1 == Some(j))
+                            .count();
+                        (run == *len).then(|| span(p + start, run))
+                    })
+                    .collect();
+                let args = some_or!(args, continue);
+                let bounds: Vec<_> = std::iter::once(whole.start)
+                    .chain(args.iter().flat_map(|a| [a.start, a.end]))
+                    .chain(std::iter::once(whole.end))
+                    .collect();
+                if bounds.windows(2).step_by(2).all(|w| w[0] < w[1]) {
+                    uses.push(MacroUse {
+                        name,
+                        span: whole,
+                        args,
+                    });
+                }
+            }
+        }
+        pos += expanded.len() + 1;
+    }
+    uses.sort_by_key(|u| u.span.start);
+    uses
+}
+
+/// A token with the index of the macro argument it comes from.
+type Token<'a> = (&'a str, Option<usize>);
+
+/// A macro used in a line, its expansion, and the number of tokens of each argument after
+/// expansion.
+type Expansion<'a, 'b> = (&'a str, Vec<Token<'b>>, Vec<usize>);
+
+/// Expands the macros in `tokens` as the preprocessor does, except for `#` and `##`. The tokens
+/// of an expansion take the index of the token expanded, and the tokens of the arguments keep
+/// theirs.
+fn expand<'a>(
+    tokens: &[Token<'a>],
+    defines: &'a BTreeMap<String, Macro>,
+    disabled: &mut Vec<&'a str>,
+) -> Option<Vec<Token<'a>>> {
+    let mut expanded = vec![];
+    let mut i = 0;
+    while i < tokens.len() {
+        let (token, index) = tokens[i];
+        let (name, m) = some_or!(
+            defines
+                .get_key_value(token)
+                .filter(|(name, _)| !disabled.contains(&name.as_str())),
+            {
+                expanded.push(tokens[i]);
+                i += 1;
+                continue;
+            }
+        );
+        let body = tokenize(&m.body).into_iter().map(|(_, t)| (t, index));
+        let body: Vec<_> = match &m.params {
+            None => {
+                i += 1;
+                body.collect()
+            }
+            Some(params) => {
+                if tokens.get(i + 1).map(|t| t.0) != Some("(") {
+                    expanded.push(tokens[i]);
+                    i += 1;
+                    continue;
+                }
+                let (mut args, close) = split_arguments(&tokens[i + 1..])?;
+                if params.is_empty() && args.len() == 1 && args[0].is_empty() {
+                    args.clear();
+                }
+                if args.len() != params.len() {
+                    return None;
+                }
+                let args = args
+                    .iter()
+                    .map(|a| expand(a, defines, disabled))
+                    .collect::<Option<Vec<_>>>()?;
+                i += close + 2;
+                body.flat_map(|t| match params.iter().position(|p| p == t.0) {
+                    Some(j) => args[j].clone(),
+                    None => vec![t],
+                })
+                .collect()
+            }
+        };
+        disabled.push(name);
+        expanded.extend(expand(&body, defines, disabled)?);
+        disabled.pop();
+    }
+    Some(expanded)
+}
+
+/// Splits the arguments of a macro use in `tokens`, which start with the opening parenthesis,
+/// and returns them with the index of the closing parenthesis.
+fn split_arguments<'a>(tokens: &[Token<'a>]) -> Option<(Vec<Vec<Token<'a>>>, usize)> {
+    let mut args = vec![vec![]];
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.0 {
+            "(" => {
+                depth += 1;
+                if depth == 1 {
+                    continue;
+                }
+            }
+            ")" => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((args, i));
+                }
+            }
+            "," if depth == 1 => {
+                args.push(vec![]);
+                continue;
+            }
+            _ => {}
+        }
+        if depth == 0 {
+            return None;
+        }
+        args.last_mut().unwrap().push(*token);
+    }
+    None
+}
+
+/// Returns the positions of the non-overlapping occurrences of `pattern` in `tokens`.
+fn occurrences(tokens: &[&str], pattern: &[&str]) -> Vec<usize> {
+    let mut positions = vec![];
+    let mut i = 0;
+    while i + pattern.len() <= tokens.len() {
+        if tokens[i..i + pattern.len()] == *pattern {
+            positions.push(i);
+            i += pattern.len();
+        } else {
+            i += 1;
+        }
+    }
+    positions
+}
+
+/// Splits C code into tokens with their offsets. Identifiers, numbers, and literals are single
+/// tokens, every other character is a token, and comments are skipped.
+fn tokenize(code: &str) -> Vec<(usize, &str)> {
+    let mut tokens = vec![];
+    let mut chars = code.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let rest = &code[start..];
+        let end = if c.is_whitespace() {
+            continue;
+        } else if rest.starts_with("//") {
+            break;
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            let end = comment.find("*/").map_or(code.len(), |i| start + i + 4);
+            while chars.next_if(|(i, _)| *i < end).is_some() {}
+            continue;
+        } else if c == '"' || c == '\'' {
+            let mut escaped = false;
+            let mut end = code.len();
+            for (i, d) in chars.by_ref() {
+                if d == c && !escaped {
+                    end = i + 1;
+                    break;
+                }
+                escaped = d == '\\' && !escaped;
+            }
+            end
+        } else if is_identifier_char(c) {
+            let number = c.is_ascii_digit();
+            while chars
+                .next_if(|(_, d)| is_identifier_char(*d) || number && *d == '.')
+                .is_some()
+            {}
+            chars.peek().map_or(code.len(), |(i, _)| *i)
+        } else {
+            start + c.len_utf8()
+        };
+        tokens.push((start, &code[start..end]));
+    }
+    tokens
+}
+
+/// Checks whether the body of a macro, split into `tokens`, parses as an expression.
+fn is_expression(body: &str, tokens: &[&str], type_names: &BTreeSet<String>) -> bool {
+    let mut depth = 0;
+    for token in tokens {
+        match *token {
+            "(" => depth += 1,
+            ")" if depth == 0 => return false,
+            ")" => depth -= 1,
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return false;
+    }
+    let typedefs: BTreeSet<_> = tokens
+        .iter()
+        .enumerate()
+        .filter(|(i, t)| {
+            type_names.contains(**t)
+                && (*i == 0 || !matches!(tokens[i - 1], "struct" | "union" | "enum"))
+        })
+        .map(|(_, t)| format!("typedef int {};\n", t))
+        .collect();
+    let code = format!(
+        "{}void f(void) {{ ({}); }}\n",
+        typedefs.into_iter().collect::<String>(),
+        body
+    );
+    driver::parse_preprocessed(&Config::with_gcc(), code).is_ok()
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with(|c: char| c.is_ascii_digit())
+        && s.chars().all(is_identifier_char)
+}
+
+fn identifiers(line: &str) -> impl Iterator<Item = &str> {
+    line.split(|c: char| !is_identifier_char(c))
+        .filter(|s| is_identifier(s))
+}
+
+/// Gives a distinct name to each `static` function or variable whose name is also defined in
+/// another file. The result maps each translation unit to its renamed symbols.
+fn find_local_names(
+    parses: &[(String, Parse, Vec<Span>)],
+) -> BTreeMap<String, BTreeMap<String, String>> {
+    let mut definitions: BTreeMap<&str, BTreeSet<(&str, bool)>> = BTreeMap::new();
+    let mut statics = vec![];
+    for (path, parse, lib_spans) in parses {
+        let files = file_spans(parse);
+        let file_of = |span: Span| {
+            files
+                .iter()
+                .find(|(s, _)| s.start <= span.start && span.start < s.end)
+                .map_or(path.as_str(), |(_, f)| *f)
+        };
+        for decl in &parse.unit.0 {
+            if lib_spans.iter().any(|s| overlap(*s, decl.span)) {
+                continue;
+            }
+            let (names, stc) = match &decl.node {
+                ExternalDeclaration::Declaration(d) => {
+                    if is_typedef(&d.node) || is_extern(&d.node) {
+                        continue;
+                    }
+                    let names = d
+                        .node
+                        .declarators
+                        .iter()
+                        .filter(|d| !is_function_proto(&d.node))
+                        .map(|d| declarator_name(&d.node.declarator.node))
+                        .collect::<Vec<_>>();
+                    (names, is_static(&d.node.specifiers))
+                }
+                ExternalDeclaration::FunctionDefinition(f) => {
+                    let name = get_identifier(&f.node.declarator.node).unwrap();
+                    (vec![name.node.name.as_str()], is_static(&f.node.specifiers))
+                }
+                _ => continue,
+            };
+            let file = file_of(decl.span);
+            for name in names {
+                definitions.entry(name).or_default().insert((file, stc));
+                if stc {
+                    statics.push((path.as_str(), name, file));
+                }
+            }
+        }
+    }
+
+    let mut used: BTreeSet<String> = definitions.keys().map(|s| s.to_string()).collect();
+    let mut keys: BTreeMap<(&str, &str), String> = BTreeMap::new();
+    let mut local_names: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    for (path, name, file) in statics {
+        let files: BTreeSet<_> = definitions[name].iter().map(|(f, _)| *f).collect();
+        if files.len() < 2 {
+            continue;
+        }
+        let key = keys.entry((name, file)).or_insert_with(|| {
+            let stem = Path::new(file)
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or(file);
+            let stem: String = stem
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            let mut key = format!("{}_{}", name, stem);
+            let mut i = 1;
+            while used.contains(&key) {
+                key = format!("{}_{}{}", name, stem, i);
+                i += 1;
+            }
+            used.insert(key.clone());
+            key
+        });
+        local_names
+            .entry(path.to_string())
+            .or_default()
+            .insert(name.to_string(), key.clone());
+    }
+    local_names
+}
+
+/// Matches `s` against `pattern`, where `*` matches any sequence and `?` any character.
+pub(crate) fn glob_match(pattern: &str, s: &str) -> bool {
+    let pattern: Vec<_> = pattern.chars().collect();
+    let s: Vec<_> = s.chars().collect();
+    let (mut p, mut i) = (0, 0);
+    let mut star = None;
+    while i < s.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == s[i]) {
+            p += 1;
+            i += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, i));
+            p += 1;
+        } else if let Some((sp, si)) = star {
+            p = sp + 1;
+            i = si + 1;
+            star = Some((sp, si + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
 }
 
 pub fn overlap(s1: Span, s2: Span) -> bool {
@@ -879,6 +2513,13 @@ fn is_extern(decl: &Declaration) -> bool {
     })
 }
 
+fn is_static(specifiers: &[Node<DeclarationSpecifier>]) -> bool {
+    specifiers.iter().any(|s| match &s.node {
+        DeclarationSpecifier::StorageClass(s) => matches!(s.node, StorageClassSpecifier::Static),
+        _ => false,
+    })
+}
+
 fn is_const(decl: &Declaration) -> bool {
     decl.specifiers.iter().any(|s| match &s.node {
         DeclarationSpecifier::TypeQualifier(t) => matches!(t.node, TypeQualifier::Const),
@@ -886,11 +2527,283 @@ fn is_const(decl: &Declaration) -> bool {
     })
 }
 
+/// A struct, union, or enum definition in a file-scope declaration.
+struct TypeDefinition<'a> {
+    specifier: &'a Node<TypeSpecifier>,
+    /// The tag, or a name synthesized from where the type appears. `None` for anonymous types
+    /// named by a typedef and for those that cannot be referred to.
+    name: Option<String>,
+    /// Whether the definition appears inside another struct or union.
+    nested: bool,
+    /// Whether the definition has no tag.
+    anonymous: bool,
+}
+
+/// Finds the type definitions in a file-scope declaration, including those nested in struct
+/// fields. Anonymous types are named after the variable (`x_type`), the enclosing type and field
+/// (`outer_field`), or their first enumerator (`A_enum`) so that each parse gives the same names.
+/// A numeric suffix is appended to a synthesized name already declared as a tag or a typedef.
+fn type_definitions<'a>(
+    decl: &'a Declaration,
+    declared: &BTreeSet<String>,
+) -> Vec<TypeDefinition<'a>> {
+    let typedef = is_typedef(decl);
+    let declarator = decl
+        .declarators
+        .first()
+        .map(|d| declarator_name(&d.node.declarator.node));
+    let mut defs = vec![];
+    for s in &decl.specifiers {
+        if let DeclarationSpecifier::TypeSpecifier(t) = &s.node {
+            let context = declarator.map(|d| {
+                if typedef {
+                    d.to_string()
+                } else {
+                    fresh_type_name(format!("{}_type", d), declared)
+                }
+            });
+            collect_type_definitions(t, context, typedef, false, declared, &mut defs);
+        }
+    }
+    defs
+}
+
+fn collect_type_definitions<'a>(
+    t: &'a Node<TypeSpecifier>,
+    context: Option<String>,
+    typedef: bool,
+    nested: bool,
+    declared: &BTreeSet<String>,
+    defs: &mut Vec<TypeDefinition<'a>>,
+) {
+    match &t.node {
+        TypeSpecifier::Struct(s) => {
+            let fields = some_or!(&s.node.declarations, return);
+            let tag = s.node.identifier.as_ref().map(|id| id.node.name.clone());
+            let anonymous = tag.is_none();
+            let owner = tag.clone().or_else(|| context.clone());
+            let name = if typedef { tag } else { tag.or(context) };
+            defs.push(TypeDefinition {
+                specifier: t,
+                name,
+                nested,
+                anonymous,
+            });
+            let owner = some_or!(owner, return);
+            for field in fields {
+                let f = if let StructDeclaration::Field(f) = &field.node {
+                    f
+                } else {
+                    continue;
+                };
+                // Anonymous members stay inline because their fields belong to the enclosing type.
+                let field_name = f
+                    .node
+                    .declarators
+                    .first()
+                    .and_then(|d| d.node.declarator.as_ref())
+                    .map(|d| declarator_name(&d.node));
+                for s in &f.node.specifiers {
+                    if let SpecifierQualifier::TypeSpecifier(t) = &s.node {
+                        let context = field_name
+                            .map(|n| fresh_type_name(format!("{}_{}", owner, n), declared));
+                        collect_type_definitions(t, context, false, true, declared, defs);
+                    }
+                }
+            }
+        }
+        TypeSpecifier::Enum(e) => {
+            let first = some_or!(e.node.enumerators.first(), return);
+            let tag = e.node.identifier.as_ref().map(|id| id.node.name.clone());
+            let anonymous = tag.is_none();
+            let name = if typedef {
+                tag
+            } else {
+                let first = &first.node.identifier.node.name;
+                tag.or(context)
+                    .or_else(|| Some(fresh_type_name(format!("{}_enum", first), declared)))
+            };
+            defs.push(TypeDefinition {
+                specifier: t,
+                name,
+                nested,
+                anonymous,
+            });
+        }
+        _ => (),
+    }
+}
+
+fn fresh_type_name(name: String, declared: &BTreeSet<String>) -> String {
+    if !declared.contains(&name) {
+        return name;
+    }
+    (1..)
+        .map(|i| format!("{}{}", name, i))
+        .find(|n| !declared.contains(n))
+        .unwrap()
+}
+
+/// Packs the bit-fields of a struct. Returns `None` if the struct has no bit-fields or if they
+/// cannot be packed deterministically, in which case the struct is translated as written.
+fn bit_fields(declarations: &[Node<StructDeclaration>]) -> Option<BitFields> {
+    let mut runs = vec![];
+    let mut units: Vec<usize> = vec![];
+    let mut fields = vec![];
+    let mut run: Option<(Span, usize)> = None;
+    let mut offset = None;
+    for d in declarations {
+        let f = if let StructDeclaration::Field(f) = &d.node {
+            f
+        } else {
+            continue;
+        };
+        let declarators = &f.node.declarators;
+        if declarators.iter().all(|d| d.node.bit_width.is_none()) {
+            if let Some((span, start)) = run.take() {
+                runs.push((span, start..units.len()));
+            }
+            offset = None;
+            continue;
+        }
+        if declarators.iter().any(|d| d.node.bit_width.is_none()) {
+            return None;
+        }
+        let (bits, signed, boolean) = bit_field_type(&f.node.specifiers)?;
+        match &mut run {
+            Some((span, _)) => span.end = f.span.end,
+            None => run = Some((f.span, units.len())),
+        }
+        for d in declarators {
+            let width = integer_constant(d.node.bit_width.as_ref()?)?;
+            if width > bits {
+                return None;
+            }
+            if width == 0 {
+                offset = None;
+                continue;
+            }
+            let o = match (offset, units.last_mut()) {
+                (Some(o), Some(unit)) if o + width <= (*unit).max(bits) => {
+                    *unit = (*unit).max(bits);
+                    o
+                }
+                _ => {
+                    units.push(bits);
+                    0
+                }
+            };
+            offset = Some(o + width);
+            if let Some(declarator) = &d.node.declarator {
+                fields.push(BitField {
+                    name: declarator_name(&declarator.node).to_string(),
+                    unit: units.len() - 1,
+                    offset: o,
+                    width,
+                    bits,
+                    signed,
+                    boolean,
+                });
+            }
+        }
+    }
+    if let Some((span, start)) = run {
+        runs.push((span, start..units.len()));
+    }
+    if fields.is_empty() {
+        return None;
+    }
+    Some(BitFields {
+        runs,
+        units,
+        fields,
+    })
+}
+
+/// Returns the width in bits of the declared type of a bit-field, whether it is signed, and
+/// whether it is `_Bool`.
+fn bit_field_type(specifiers: &[Node<SpecifierQualifier>]) -> Option<(usize, bool, bool)> {
+    let mut bits = 32;
+    let mut signed = true;
+    let mut boolean = false;
+    for s in specifiers {
+        let t = if let SpecifierQualifier::TypeSpecifier(t) = &s.node {
+            t
+        } else {
+            continue;
+        };
+        match &t.node {
+            TypeSpecifier::Char => bits = 8,
+            TypeSpecifier::Short => bits = 16,
+            TypeSpecifier::Int => (),
+            TypeSpecifier::Long => bits = 64,
+            TypeSpecifier::Signed => signed = true,
+            TypeSpecifier::Unsigned => signed = false,
+            TypeSpecifier::Bool => {
+                bits = 8;
+                signed = false;
+                boolean = true;
+            }
+            TypeSpecifier::Enum(_) => signed = false,
+            TypeSpecifier::TypedefName(x) => {
+                let name = x.node.name.trim_start_matches('_').replacen("u_", "u", 1);
+                let (unsigned, name) = match name.strip_prefix('u') {
+                    Some(name) => (true, name),
+                    None => (false, name.as_str()),
+                };
+                let n = name.strip_prefix("int")?.strip_suffix("_t")?;
+                bits = n.parse().ok()?;
+                signed = !unsigned;
+            }
+            _ => return None,
+        }
+    }
+    Some((bits, signed, boolean))
+}
+
+fn integer_constant(expr: &Node<Expression>) -> Option<usize> {
+    if let Expression::Constant(c) = &expr.node {
+        if let Constant::Integer(i) = &c.node {
+            let radix = match i.base {
+                IntegerBase::Decimal => 10,
+                IntegerBase::Octal => 8,
+                IntegerBase::Hexadecimal => 16,
+                IntegerBase::Binary => 2,
+            };
+            return usize::from_str_radix(&i.number, radix).ok();
+        }
+    }
+    None
+}
+
+/// Collects the custom types a node refers to. Nested and anonymous definitions whose spans are
+/// in the map are recorded as references to their synthesized names instead of being visited.
 #[derive(Default)]
-struct TypeSpecifierVisitor<'ast>(Vec<TypeDependency<'ast>>);
+struct TypeSpecifierVisitor<'ast>(Vec<TypeDependency<'ast>>, BTreeMap<usize, &'ast str>);
+
+impl<'ast> TypeSpecifierVisitor<'ast> {
+    fn new(nested: BTreeMap<usize, &'ast str>) -> Self {
+        Self(vec![], nested)
+    }
+}
 
 impl<'ast> Visit<'ast> for TypeSpecifierVisitor<'ast> {
     fn visit_type_specifier(&mut self, type_specifier: &'ast TypeSpecifier, span: &'ast Span) {
+        if let Some(x) = self.1.get(&span.start) {
+            let typ = match type_specifier {
+                TypeSpecifier::Struct(s) => match &s.node.kind.node {
+                    StructKind::Struct => CustomType::mk_struct(x),
+                    StructKind::Union => CustomType::mk_union(x),
+                },
+                _ => CustomType::mk_enum(x),
+            };
+            self.0.push(TypeDependency {
+                typ,
+                span: *span,
+                enumerator: None,
+            });
+            return;
+        }
         match type_specifier {
             TypeSpecifier::Struct(s) => {
                 if let Some(x) = &s.node.identifier {
@@ -900,7 +2813,11 @@ impl<'ast> Visit<'ast> for TypeSpecifierVisitor<'ast> {
                             StructKind::Struct => CustomType::mk_struct(x),
                             StructKind::Union => CustomType::mk_union(x),
                         };
-                        self.0.push(TypeDependency { typ, span: *span });
+                        self.0.push(TypeDependency {
+                            typ,
+                            span: *span,
+                            enumerator: None,
+                        });
                     }
                 }
             }
@@ -908,13 +2825,21 @@ impl<'ast> Visit<'ast> for TypeSpecifierVisitor<'ast> {
                 if let Some(x) = &e.node.identifier {
                     if e.node.enumerators.is_empty() {
                         let typ = CustomType::mk_enum(x.node.name.as_str());
-                        self.0.push(TypeDependency { typ, span: *span });
+                        self.0.push(TypeDependency {
+                            typ,
+                            span: *span,
+                            enumerator: None,
+                        });
                     }
                 }
             }
             TypeSpecifier::TypedefName(x) => {
                 let typ = CustomType::mk_typedef(x.node.name.as_str());
-                self.0.push(TypeDependency { typ, span: *span });
+                self.0.push(TypeDependency {
+                    typ,
+                    span: *span,
+                    enumerator: None,
+                });
             }
             _ => (),
         }
@@ -925,12 +2850,96 @@ impl<'ast> Visit<'ast> for TypeSpecifierVisitor<'ast> {
 #[derive(Default)]
 struct CalleeVisitor<'ast>(Vec<&'ast Node<Identifier>>);
 
-impl<'ast> Visit<'ast> for CalleeVisitor<'ast> {
-    fn visit_call_expression(&mut self, call_expression: &'ast CallExpression, span: &'ast Span) {
-        if let Expression::Identifier(x) = &call_expression.callee.node {
-            self.0.push(x);
+impl<'ast> Visit<'ast> for CalleeVisitor<'ast> {
+    fn visit_call_expression(&mut self, call_expression: &'ast CallExpression, span: &'ast Span) {
+        if let Expression::Identifier(x) = &call_expression.callee.node {
+            self.0.push(x);
+        }
+        visit::visit_call_expression(self, call_expression, span)
+    }
+}
+
+/// How a function is used without being called.
+enum PointerUse<'ast> {
+    /// Passed to the callee as the argument at the index.
+    Argument(&'ast Node<Expression>, usize),
+    /// Assigned to the left-hand side.
+    Assignment(&'ast Node<Expression>),
+    Value,
+}
+
+#[derive(Default)]
+struct FunctionPointerVisitor<'ast> {
+    uses: Vec<(&'ast Node<Identifier>, PointerUse<'ast>)>,
+    /// The callees and the numbers of arguments of the calls.
+    calls: Vec<(&'ast Node<Expression>, usize)>,
+    /// The starts of the identifiers already classified.
+    visited: BTreeSet<usize>,
+}
+
+impl<'ast> Visit<'ast> for FunctionPointerVisitor<'ast> {
+    fn visit_call_expression(&mut self, call_expression: &'ast CallExpression, span: &'ast Span) {
+        if let Expression::Identifier(x) = &call_expression.callee.node {
+            self.visited.insert(x.span.start);
+        }
+        self.calls
+            .push((&call_expression.callee, call_expression.arguments.len()));
+        for (i, arg) in call_expression.arguments.iter().enumerate() {
+            if let Some(x) = value_identifier(&arg.node) {
+                self.visited.insert(x.span.start);
+                let u = PointerUse::Argument(&call_expression.callee, i);
+                self.uses.push((x, u));
+            }
+        }
+        visit::visit_call_expression(self, call_expression, span)
+    }
+
+    fn visit_binary_operator_expression(
+        &mut self,
+        binary_operator_expression: &'ast BinaryOperatorExpression,
+        span: &'ast Span,
+    ) {
+        let BinaryOperatorExpression { operator, lhs, rhs } = binary_operator_expression;
+        if operator.node == BinaryOperator::Assign {
+            if let Some(x) = value_identifier(&rhs.node) {
+                self.visited.insert(x.span.start);
+                self.uses.push((x, PointerUse::Assignment(lhs)));
+            }
+        }
+        visit::visit_binary_operator_expression(self, binary_operator_expression, span)
+    }
+
+    fn visit_expression(&mut self, expression: &'ast Expression, span: &'ast Span) {
+        if let Expression::Identifier(x) = expression {
+            if self.visited.insert(x.span.start) {
+                self.uses.push((x, PointerUse::Value));
+            }
+        }
+        visit::visit_expression(self, expression, span)
+    }
+}
+
+/// Finds `f` in `f`, `&f` and casts of them.
+fn value_identifier(expr: &Expression) -> Option<&Node<Identifier>> {
+    match expr {
+        Expression::Identifier(x) => Some(x),
+        Expression::UnaryOperator(e) if e.node.operator.node == UnaryOperator::Address => {
+            value_identifier(&e.node.operand.node)
+        }
+        Expression::Cast(e) => value_identifier(&e.node.expression.node),
+        _ => None,
+    }
+}
+
+/// Finds the variable or field, which is indicated by `true`, assigned by an expression.
+fn assigned_name(expr: &Expression) -> Option<(&str, bool)> {
+    match expr {
+        Expression::Identifier(x) => Some((x.node.name.as_str(), false)),
+        Expression::Member(e) => Some((e.node.identifier.node.name.as_str(), true)),
+        Expression::BinaryOperator(e) if e.node.operator.node == BinaryOperator::Index => {
+            assigned_name(&e.node.lhs.node)
         }
-        visit::visit_call_expression(self, call_expression, span)
+        _ => None,
     }
 }
 
@@ -981,6 +2990,34 @@ fn get_local_variables(function_definition: &FunctionDefinition) -> Vec<&str> {
     visitor.0
 }
 
+/// Collects the tags and the typedef names in a translation unit.
+#[derive(Default)]
+struct TypeNameVisitor(BTreeSet<String>);
+
+impl<'ast> Visit<'ast> for TypeNameVisitor {
+    fn visit_declaration(&mut self, declaration: &'ast Declaration, span: &'ast Span) {
+        if is_typedef(declaration) {
+            let names = declarator_names(declaration).into_iter();
+            self.0.extend(names.map(|n| n.to_string()));
+        }
+        visit::visit_declaration(self, declaration, span)
+    }
+
+    fn visit_struct_type(&mut self, struct_type: &'ast StructType, span: &'ast Span) {
+        if let Some(x) = &struct_type.identifier {
+            self.0.insert(x.node.name.clone());
+        }
+        visit::visit_struct_type(self, struct_type, span)
+    }
+
+    fn visit_enum_type(&mut self, enum_type: &'ast EnumType, span: &'ast Span) {
+        if let Some(x) = &enum_type.identifier {
+            self.0.insert(x.node.name.clone());
+        }
+        visit::visit_enum_type(self, enum_type, span)
+    }
+}
+
 #[derive(Default)]
 struct NameVisitor<'ast>(Vec<(&'ast str, Span)>);
 
@@ -1008,6 +3045,72 @@ fn get_identifier(decl: &Declarator) -> Option<&Node<Identifier>> {
     }
 }
 
+fn parameter_names(decl: &Declarator) -> Vec<&str> {
+    match function_derived(decl) {
+        DerivedDeclarator::Function(d) => d
+            .node
+            .parameters
+            .iter()
+            .filter_map(|p| get_identifier(&p.node.declarator.as_ref()?.node))
+            .map(|x| x.node.name.as_str())
+            .collect(),
+        DerivedDeclarator::KRFunction(ps) => ps.iter().map(|x| x.node.name.as_str()).collect(),
+        _ => vec![],
+    }
+}
+
+/// Checks if a declarator declares a function pointer or an array of them.
+fn is_function_pointer(decl: &Declarator) -> bool {
+    let mut derived = derived_declarators(decl)
+        .into_iter()
+        .skip_while(|d| matches!(d, DerivedDeclarator::Array(_)));
+    matches!(derived.next(), Some(DerivedDeclarator::Pointer(_)))
+        && matches!(
+            derived.next(),
+            Some(DerivedDeclarator::Function(_) | DerivedDeclarator::KRFunction(_))
+        )
+}
+
+/// Checks if a declarator declares a pointer to a variadic function or an array of them.
+fn is_variadic_function_pointer(decl: &Declarator) -> bool {
+    let mut derived = derived_declarators(decl)
+        .into_iter()
+        .skip_while(|d| matches!(d, DerivedDeclarator::Array(_)));
+    matches!(derived.next(), Some(DerivedDeclarator::Pointer(_)))
+        && matches!(
+            derived.next(),
+            Some(DerivedDeclarator::Function(d)) if d.node.ellipsis == Ellipsis::Some
+        )
+}
+
+/// Checks if a function declarator takes variable arguments.
+pub fn is_variadic(decl: &Declarator) -> bool {
+    matches!(
+        function_derived(decl),
+        DerivedDeclarator::Function(d) if d.node.ellipsis == Ellipsis::Some
+    )
+}
+
+/// Lists the derived declarators from the one applied to the name first.
+fn derived_declarators(decl: &Declarator) -> Vec<&DerivedDeclarator> {
+    let mut derived = match &decl.kind.node {
+        DeclaratorKind::Declarator(d) => derived_declarators(&d.node),
+        _ => vec![],
+    };
+    derived.extend(decl.derived.iter().rev().map(|d| &d.node));
+    derived
+}
+
+fn has_typedef<'a>(
+    specifiers: impl IntoIterator<Item = &'a TypeSpecifier>,
+    typedefs: &BTreeSet<String>,
+) -> bool {
+    specifiers.into_iter().any(|s| match s {
+        TypeSpecifier::TypedefName(x) => typedefs.contains(&x.node.name),
+        _ => false,
+    })
+}
+
 fn function_derived(decl: &Declarator) -> &DerivedDeclarator {
     for derived in &decl.derived {
         if matches!(
@@ -1066,126 +3169,373 @@ fn is_function_proto(decl: &InitDeclarator) -> bool {
         })
 }
 
-fn type_of(
-    specifiers: &[Node<DeclarationSpecifier>],
-    mut declarator: Option<&Node<Declarator>>,
-) -> Type {
-    let ty = specifiers
-        .iter()
-        .find_map(|node| match &node.node {
-            DeclarationSpecifier::TypeSpecifier(t) => Some(&t.node),
-            _ => None,
-        })
-        .unwrap();
-    let mut ty = match ty {
-        TypeSpecifier::Void => compiler::UNIT,
-        TypeSpecifier::Char
-        | TypeSpecifier::Short
-        | TypeSpecifier::Int
-        | TypeSpecifier::Long
-        | TypeSpecifier::Signed
-        | TypeSpecifier::Unsigned => Type::from_name("int".to_string()),
-        TypeSpecifier::Float | TypeSpecifier::Double => Type::from_name("float".to_string()),
-        TypeSpecifier::Bool => Type::from_name("int".to_string()),
-        TypeSpecifier::Complex => todo!("{:?}", ty),
-        TypeSpecifier::Atomic(_) => todo!("{:?}", ty),
-        TypeSpecifier::Struct(s) => {
-            Type::from_name(s.node.identifier.as_ref().unwrap().node.name.clone())
+/// File-scope declarations of a translation unit, used to resolve `typedef` names and `typeof`.
+struct TypeTable<'a> {
+    typedefs: BTreeMap<&'a str, (Vec<&'a TypeSpecifier>, &'a Node<Declarator>)>,
+    variables: BTreeMap<&'a str, (Vec<&'a TypeSpecifier>, &'a Node<Declarator>)>,
+    /// `typedef`s of the project, which are referred to by their names.
+    project_typedefs: &'a BTreeSet<String>,
+}
+
+impl<'a> TypeTable<'a> {
+    fn new(unit: &'a TranslationUnit, project_typedefs: &'a BTreeSet<String>) -> Self {
+        let mut typedefs = BTreeMap::new();
+        let mut variables = BTreeMap::new();
+        for decl in &unit.0 {
+            if let ExternalDeclaration::Declaration(decl) = &decl.node {
+                let specifiers = specifier_types(&decl.node.specifiers);
+                let table = if is_typedef(&decl.node) {
+                    &mut typedefs
+                } else {
+                    &mut variables
+                };
+                for d in &decl.node.declarators {
+                    let name = declarator_name(&d.node.declarator.node);
+                    table
+                        .entry(name)
+                        .or_insert_with(|| (specifiers.clone(), &d.node.declarator));
+                }
+            }
         }
-        TypeSpecifier::Enum(e) => {
-            Type::from_name(e.node.identifier.as_ref().unwrap().node.name.clone())
+        Self {
+            typedefs,
+            variables,
+            project_typedefs,
         }
-        TypeSpecifier::TypedefName(t) => {
-            let name = &t.node.name;
-            if name.contains("size_t")
-                || name.to_lowercase().contains("int")
-                || name.contains("pid_t")
-            {
-                Type::from_name("int".to_string())
+    }
+
+    fn declaration_type(
+        &self,
+        specifiers: &[Node<DeclarationSpecifier>],
+        declarator: Option<&Node<Declarator>>,
+    ) -> Type {
+        self.type_of(&specifier_types(specifiers), declarator)
+    }
+
+    fn type_name(&self, type_name: &TypeName) -> Type {
+        let specifiers: Vec<_> = type_name
+            .specifiers
+            .iter()
+            .filter_map(|s| match &s.node {
+                SpecifierQualifier::TypeSpecifier(t) => Some(&t.node),
+                _ => None,
+            })
+            .collect();
+        self.type_of(&specifiers, type_name.declarator.as_ref())
+    }
+
+    fn type_of(
+        &self,
+        specifiers: &[&TypeSpecifier],
+        mut declarator: Option<&Node<Declarator>>,
+    ) -> Type {
+        let mut ty = self.base_type(specifiers);
+        while let Some(decl) = &declarator {
+            for d in &decl.node.derived {
+                if matches!(d.node, DerivedDeclarator::Pointer(_)) {
+                    ty = Type::Ptr(Box::new(ty), true);
+                }
+            }
+            for d in &decl.node.derived {
+                if matches!(d.node, DerivedDeclarator::Array(_)) {
+                    ty = Type::Array(Box::new(ty), "_".to_string());
+                }
+            }
+            if let DeclaratorKind::Declarator(inner) = &decl.node.kind.node {
+                declarator = Some(inner);
             } else {
-                Type::from_name(name.clone())
+                declarator = None;
             }
         }
-        TypeSpecifier::TypeOf(_) => todo!("{:?}", ty),
-        TypeSpecifier::TS18661Float(_) => todo!("{:?}", ty),
-    };
-    while let Some(decl) = &declarator {
-        for d in &decl.node.derived {
-            if matches!(d.node, DerivedDeclarator::Pointer(_)) {
-                ty = Type::Ptr(Box::new(ty), true);
-            }
+        ty
+    }
+
+    fn base_type(&self, specifiers: &[&TypeSpecifier]) -> Type {
+        if specifiers
+            .iter()
+            .any(|s| matches!(s, TypeSpecifier::Complex))
+        {
+            return Type::from_name("complex".to_string());
         }
-        for d in &decl.node.derived {
-            if matches!(d.node, DerivedDeclarator::Array(_)) {
-                ty = Type::Array(Box::new(ty), "_".to_string());
+        // A declaration without a type specifier is implicitly `int`.
+        let ty = some_or!(
+            specifiers.first(),
+            return Type::from_name("int".to_string())
+        );
+        match ty {
+            TypeSpecifier::Void => compiler::UNIT,
+            TypeSpecifier::Char
+            | TypeSpecifier::Short
+            | TypeSpecifier::Int
+            | TypeSpecifier::Long
+            | TypeSpecifier::Signed
+            | TypeSpecifier::Unsigned
+            | TypeSpecifier::Bool => Type::from_name("int".to_string()),
+            TypeSpecifier::Float | TypeSpecifier::Double | TypeSpecifier::TS18661Float(_) => {
+                Type::from_name("float".to_string())
             }
+            TypeSpecifier::Complex => unreachable!(),
+            TypeSpecifier::Atomic(t) => self.type_name(&t.node),
+            TypeSpecifier::Struct(s) => s
+                .node
+                .identifier
+                .as_ref()
+                .map_or(Type::Infer, |id| Type::from_name(id.node.name.clone())),
+            TypeSpecifier::Enum(e) => e
+                .node
+                .identifier
+                .as_ref()
+                .map_or(Type::Infer, |id| Type::from_name(id.node.name.clone())),
+            TypeSpecifier::TypedefName(t) => self.typedef(&t.node.name),
+            TypeSpecifier::TypeOf(t) => match &t.node {
+                TypeOf::Type(t) => self.type_name(&t.node),
+                TypeOf::Expression(e) => match &e.node {
+                    Expression::Identifier(id) => self
+                        .variables
+                        .get(id.node.name.as_str())
+                        .map_or(Type::Infer, |(s, d)| self.type_of(s, Some(d))),
+                    _ => Type::Infer,
+                },
+            },
         }
-        if let DeclaratorKind::Declarator(inner) = &decl.node.kind.node {
-            declarator = Some(inner);
+    }
+
+    /// Resolves a library `typedef` to an arithmetic type or `void`. Other `typedef`s are
+    /// referred to by their names.
+    fn typedef(&self, name: &str) -> Type {
+        let named = Type::from_name(name.to_string());
+        if self.project_typedefs.contains(name) {
+            return named;
+        }
+        let (specifiers, declarator) = some_or!(self.typedefs.get(name), return named);
+        let ty = self.type_of(specifiers, Some(declarator));
+        let is_arithmetic = |name: &str| ty == Type::from_name(name.to_string());
+        if ty == compiler::UNIT || is_arithmetic("int") || is_arithmetic("float") {
+            ty
         } else {
-            declarator = None;
+            named
         }
     }
-    ty
+}
+
+fn specifier_types(specifiers: &[Node<DeclarationSpecifier>]) -> Vec<&TypeSpecifier> {
+    specifiers
+        .iter()
+        .filter_map(|s| match &s.node {
+            DeclarationSpecifier::TypeSpecifier(t) => Some(&t.node),
+            _ => None,
+        })
+        .collect()
 }
 
 #[derive(Serialize, Deserialize)]
 struct CompileCommand {
-    arguments: Vec<String>,
+    #[serde(default)]
+    arguments: Option<Vec<String>>,
+    #[serde(default)]
+    command: Option<String>,
     directory: PathBuf,
     file: PathBuf,
 }
 
 impl CompileCommand {
-    fn preprocess(&self) -> Option<Preprocessed> {
+    fn arguments(&self) -> Vec<String> {
+        if let Some(arguments) = &self.arguments {
+            arguments.clone()
+        } else if let Some(command) = &self.command {
+            split_shell_words(command)
+                .unwrap_or_else(|| panic!("unterminated quote in `{}`", command))
+        } else {
+            panic!("{:?} has neither arguments nor command", self.file)
+        }
+    }
+
+    fn preprocess(&self, preprocessor: &Preprocessor) -> Result<Preprocessed, Diagnostic> {
         // if self.arguments[0] != "cc" {
         //     return None;
         // }
 
-        let mut arguments = self.arguments.clone();
-        arguments.retain(|x| !x.starts_with("-g") && !x.starts_with("-O"));
-
-        let mut command = Command::new("gcc");
-        command.current_dir(&self.directory).arg("-E");
+        let mut arguments = self.arguments();
         if let Some(i) = arguments.iter().position(|x| x == "-o") {
-            command.args(&arguments[1..i]);
-            command.args(&arguments[i + 2..]);
-        } else {
-            command.args(&arguments[1..]);
-        }
-        let output = command.output().unwrap();
-        assert!(output.status.success(), "{:?}", command);
-        let long_code = String::from_utf8(output.stdout)
-            .unwrap()
-            .replace("typedef __int128", "typedef int")
-            .replace("typedef unsigned __int128", "typedef int");
-
-        command.arg("-P");
-        let output = command.output().unwrap();
-        assert!(output.status.success());
-        let code = String::from_utf8(output.stdout)
-            .unwrap()
-            .replace("typedef __int128", "typedef int")
-            .replace("typedef unsigned __int128", "typedef int");
+            arguments.drain(i..(i + 2).min(arguments.len()));
+        }
+        arguments.retain(|x| {
+            !preprocessor
+                .removed_args
+                .iter()
+                .any(|prefix| x.starts_with(prefix))
+        });
+
+        // `-dD` keeps the macro definitions in the output so that they can be recovered.
+        let run = |flag: &str| {
+            let mut command = Command::new(&preprocessor.command);
+            command
+                .current_dir(&self.directory)
+                .envs(&preprocessor.env)
+                .arg("-E")
+                .args(&arguments[1..])
+                .args(&preprocessor.extra_args)
+                .arg(flag);
+            let output = command
+                .output()
+                .map_err(|err| format!("{:?}: {}", command, err))?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(format!("{:?} failed\n{}", command, stderr.trim_end()));
+            }
+            let code = String::from_utf8(output.stdout)
+                .map_err(|err| format!("{:?}: {}", command, err))?;
+            Ok(code
+                .replace("typedef __int128", "typedef int")
+                .replace("typedef unsigned __int128", "typedef int"))
+        };
 
         let mut path = self.directory.clone();
         path.push(&self.file);
 
+        let codes = run("-dD").and_then(|long_code| Ok((run("-P")?, long_code)));
+        let (code, long_code) = codes.map_err(|message| Diagnostic {
+            path: path.to_str().unwrap().to_string(),
+            message,
+            unresolved: vec![],
+        })?;
+
         let preprocessed = Preprocessed {
             path,
+            directory: self.directory.clone(),
             code,
             long_code,
         };
-        Some(preprocessed)
+        Ok(preprocessed)
+    }
+}
+
+/// Finds the C files to translate, taking a file as it is and the `.c` files in a directory.
+fn collect_sources(path: &Path, files: &mut Vec<PathBuf>) {
+    if !path.is_dir() {
+        if !files.iter().any(|f| f == path) {
+            files.push(path.to_path_buf());
+        }
+        return;
+    }
+    let mut entries: Vec<_> = fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    entries.sort();
+    for entry in entries {
+        let hidden = entry
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| name.starts_with('.'));
+        if hidden {
+            continue;
+        }
+        if entry.is_dir() || entry.extension().map_or(false, |ext| ext == "c") {
+            collect_sources(&entry, files);
+        }
+    }
+}
+
+/// Splits `s` into words as a POSIX shell does, without expansions.
+fn split_shell_words(s: &str) -> Option<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            c @ ('"' | '\\' | '$' | '`') => word.push(c),
+                            '\n' => (),
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => (),
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => word.get_or_insert_with(String::new).push('\\'),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Some(words)
+}
+
+/// How each translation unit is preprocessed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Preprocessor {
+    /// Preprocessor executable, replacing the compiler in the compile command.
+    pub command: String,
+    /// Arguments appended to every command (e.g., `--sysroot`, `-D`, `-I`).
+    pub extra_args: Vec<String>,
+    /// Prefixes of the arguments removed from every command.
+    pub removed_args: Vec<String>,
+    /// Additional environment variables.
+    pub env: BTreeMap<String, String>,
+    /// Number of translation units preprocessed in parallel.
+    pub jobs: Option<usize>,
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self {
+            command: "gcc".to_string(),
+            extra_args: vec![],
+            removed_args: vec!["-g".to_string(), "-O".to_string()],
+            env: BTreeMap::new(),
+            jobs: None,
+        }
     }
 }
 
 struct Preprocessed {
     path: PathBuf,
+    directory: PathBuf,
     code: String,
     long_code: String,
 }
 
+#[cfg(test)]
+impl Program {
+    /// Parses preprocessed code as a single file `test.c`.
+    pub(crate) fn from_code(code: &str) -> Self {
+        let preprocessed = Preprocessed {
+            path: PathBuf::from("test.c"),
+            directory: PathBuf::new(),
+            code: code.to_string(),
+            long_code: code.to_string(),
+        };
+        Self::new(vec![preprocessed], vec![], &LibraryFilter::default())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1193,10 +3543,11 @@ mod tests {
     fn parse(code: &str) -> Program {
         let preprocessed = Preprocessed {
             path: PathBuf::from("test.c"),
+            directory: PathBuf::new(),
             code: code.to_string(),
             long_code: code.to_string(),
         };
-        Program::new(vec![preprocessed])
+        Program::new(vec![preprocessed], vec![], &LibraryFilter::default())
     }
 
     fn get_signature(code: &str) -> FunTySig {
@@ -1325,5 +3676,574 @@ mod tests {
         assert_eq!(params[0], int);
         assert_eq!(params[1], ptr(&int));
         assert_eq!(ret, int);
+
+        let FunTySig { params, ret, .. } = get_signature("int f(x, y) int *y; {}");
+        assert_eq!(params.len(), 2);
+        assert_eq!(params[0], int);
+        assert_eq!(params[1], ptr(&int));
+        assert_eq!(ret, int);
+
+        let FunTySig { params, ret, .. } = get_signature(
+            "int g; void f(_Atomic(int) a, typeof(int *) b, typeof(g) c, double _Complex d) {}",
+        );
+        assert_eq!(params.len(), 4);
+        assert_eq!(params[0], int);
+        assert_eq!(params[1], ptr(&int));
+        assert_eq!(params[2], int);
+        assert_eq!(params[3], Type::from_name("complex".to_string()));
+        assert_eq!(ret, compiler::UNIT);
+
+        let FunTySig { params, ret, .. } = get_signature(
+            "# 1 \"/usr/include/stdio.h\"
+typedef unsigned long size_t;
+typedef struct _IO_FILE FILE;
+# 1 \"test.c\"
+typedef int myint;
+size_t f(FILE *fp, myint x) {}",
+        );
+        assert_eq!(params.len(), 2);
+        assert_eq!(params[0], ptr(&Type::from_name("FILE".to_string())));
+        assert_eq!(params[1], Type::from_name("myint".to_string()));
+        assert_eq!(ret, int);
+    }
+
+    #[test]
+    fn test_variadic() {
+        let sig = get_signature("void f(int status, const char *fmt, ...) {}");
+        assert_eq!(sig.params.len(), 2);
+        assert!(sig.variadic);
+        assert!(sig.to_string().ends_with(", ...) -> ()"));
+
+        assert!(!get_signature("void f(int status) {}").variadic);
+        assert!(!get_signature("void f(x) int x; {}").variadic);
+    }
+
+    #[test]
+    fn test_variadic_function_pointers() {
+        let program = parse(
+            "typedef void (*log_t)(const char *, ...);
+struct ops { log_t log; void (*warn)(const char *, ...); int (*get)(int); };
+void (*logger)(const char *, ...);
+void run(struct ops *o, void (*f)(int, ...), int (*g)(int)) {
+    o->log(\"a\", 1);
+    o->warn(\"b\");
+    o->get(1);
+    logger(\"c\", 2, 3);
+    f(1, 2);
+    g(1);
+}",
+        );
+        assert!(program.variadic_pointer_typedefs.contains("log_t"));
+        let pointers: Vec<_> = program.variadic_function_pointers.iter().collect();
+        assert_eq!(pointers, vec!["log", "logger", "warn"]);
+
+        let functions = program.functions();
+        let run = &functions["run"];
+        assert_eq!(run.indirect_calls.len(), 6);
+        assert_eq!(
+            run.variadic_indirect_calls,
+            vec!["o->log", "o->warn", "logger", "f"]
+        );
+    }
+
+    #[test]
+    fn test_split_shell_words() {
+        let split = |s: &str| split_shell_words(s).unwrap();
+        assert_eq!(split("cc -c foo.c"), vec!["cc", "-c", "foo.c"]);
+        assert_eq!(split("  cc\t-c  foo.c "), vec!["cc", "-c", "foo.c"]);
+        assert_eq!(
+            split(r#"cc -DNAME=\"foo\" -DMSG='a b' -I"dir name" x\ y.c"#),
+            vec!["cc", "-DNAME=\"foo\"", "-DMSG=a b", "-Idir name", "x y.c"]
+        );
+        assert_eq!(
+            split(r#"cc "-DS=\"\\n\"" '' """#),
+            vec!["cc", "-DS=\"\\n\"", "", ""]
+        );
+        assert_eq!(split_shell_words("cc 'foo"), None);
+        assert_eq!(split_shell_words("cc \"foo"), None);
+    }
+
+    #[test]
+    fn test_library_filter() {
+        let filter = LibraryFilter::default();
+        assert!(filter.is_library("/usr/include/stdio.h"));
+        assert!(!filter.is_library("/usr/include/arpa/inet.h"));
+        assert!(!filter.is_library("src/main.c"));
+
+        let filter = LibraryFilter {
+            paths: vec!["/usr/*".to_string(), "/nix/store/*".to_string()],
+            exclude: vec!["*/vendor/*".to_string()],
+        };
+        assert!(filter.is_library("/nix/store/abc-glibc/include/stdio.h"));
+        assert!(!filter.is_library("/usr/src/app/vendor/foo.h"));
+        assert!(!filter.is_library("/opt/include/foo.h"));
+    }
+
+    #[test]
+    fn test_local_names() {
+        let file = |path: &str, code: &str| Preprocessed {
+            path: PathBuf::from(path),
+            directory: PathBuf::new(),
+            code: code.to_string(),
+            long_code: code.to_string(),
+        };
+        let program = Program::new(
+            vec![
+                file(
+                    "a.c",
+                    "static int usage() { return 0; } int f() { return usage(); }",
+                ),
+                file(
+                    "b.c",
+                    "static int usage(); int g() { return usage(); } static int usage() { return 1; }",
+                ),
+                file("c.c", "static int helper() { return 2; }"),
+            ],
+            vec![],
+            &LibraryFilter::default(),
+        );
+        let functions = program.functions();
+        let names: Vec<_> = functions.keys().copied().collect();
+        assert_eq!(names, vec!["f", "g", "helper", "usage_a", "usage_b"]);
+        assert!(program.proto_set.is_empty());
+        assert_eq!(functions["f"].callees[0].name, "usage_a");
+        assert_eq!(functions["g"].callees[0].name, "usage_b");
+    }
+
+    #[test]
+    fn test_unparsable_file() {
+        let dir = std::env::temp_dir().join(format!("simcrat-unparsable-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let b = dir.join("b.c");
+        let b_code = "int g(void) { return @; } int v;";
+        fs::write(&b, b_code).unwrap();
+
+        let file = |path: &Path, code: &str| Preprocessed {
+            path: path.to_path_buf(),
+            directory: PathBuf::new(),
+            code: code.to_string(),
+            long_code: code.to_string(),
+        };
+        let a_code =
+            "int g(void); int h(void); int k(void); extern int v; int f() { return g() + h() + k() + v; }";
+        let program = Program::new(
+            vec![
+                file(Path::new("a.c"), a_code),
+                file(&b, b_code),
+                file(Path::new("c.c"), "int h(void) { return 0; }"),
+            ],
+            vec![],
+            &LibraryFilter::default(),
+        );
+        fs::remove_dir_all(dir).unwrap();
+
+        let functions = program.functions();
+        let names: Vec<_> = functions.keys().copied().collect();
+        assert_eq!(names, vec!["f", "h"]);
+        assert_eq!(program.diagnostics.len(), 1);
+        let diagnostic = &program.diagnostics[0];
+        assert_eq!(diagnostic.path, b.to_str().unwrap());
+        // `k` is unresolved too, but `b.c` does not mention it.
+        assert_eq!(diagnostic.unresolved, vec!["g", "v"]);
+    }
+
+    #[test]
+    fn test_macros() {
+        let code = "# 1 \"a.c\"\n#define SIZE 16\n#define MAX(a, b) ((a) > (b) ? (a) : (b))\n#define GREETING \"hi\"\n#define EMPTY\n#undef X\nint x;\n";
+        let (code, defines) = extract_defines(code);
+        assert_eq!(code, "# 1 \"a.c\"\n\n\n\n\n\nint x;\n");
+        assert!(defines.iter().all(|(file, _, _)| *file == "a.c"));
+        let lines: Vec<_> = defines.iter().map(|(_, line, _)| *line).collect();
+        assert_eq!(lines, vec![1, 2, 3, 4]);
+        let macros: BTreeMap<_, _> = defines
+            .iter()
+            .map(|(_, _, define)| Macro::parse(define, "a.c").unwrap())
+            .map(|m| (m.name.clone(), m))
+            .collect();
+        assert_eq!(macros["SIZE"].body, "16");
+        assert_eq!(
+            macros["MAX"].params,
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(macros["MAX"].body, "((a) > (b) ? (a) : (b))");
+        assert_eq!(macros["GREETING"].body, "\"hi\"");
+        let reasons: Vec<_> = macros
+            .values()
+            .map(|m| m.skip_reason(&BTreeSet::new()))
+            .collect();
+        assert_eq!(reasons, vec![Some("empty"), None, None, None]);
+
+        let original = [
+            "int f(int x) {",
+            "  int a[SIZE];",
+            "  return x + 16 + SIZE;",
+            "  puts(GREETING);",
+            "}",
+        ];
+        let expanded = "int f(int x) {\n  int a[16];\n  return x + 16 + 16;\n  puts(\"hi\");\n}";
+        let spans: Vec<_> = macro_spans(&original, expanded, 10, &macros, &macros)
+            .into_iter()
+            .map(|u| (u.name, &expanded[u.span.start - 10..u.span.end - 10]))
+            .collect();
+        assert_eq!(spans, vec![("SIZE", "16"), ("GREETING", "\"hi\"")]);
+    }
+
+    #[test]
+    fn test_macro_expressions() {
+        let defines: BTreeMap<_, _> = [
+            "A 1",
+            "B 2",
+            "MASK (A | B)",
+            "N (1 << 4)",
+            "MAX(a, b) ((a) > (b) ? (a) : (b))",
+            "NEXT(x) ((x) + A)",
+            "STR(x) #x",
+            "LOG(...) printf(__VA_ARGS__)",
+            "LOOP do { } while (0)",
+            "CAST ((T) 1)",
+        ]
+        .into_iter()
+        .map(|define| Macro::parse(define, "a.c").unwrap())
+        .map(|m| (m.name.clone(), m))
+        .collect();
+        let type_names = BTreeSet::from(["T".to_string()]);
+        let reasons: BTreeMap<_, _> = defines
+            .values()
+            .filter_map(|m| Some((m.name.as_str(), m.skip_reason(&type_names)?)))
+            .collect();
+        assert_eq!(
+            reasons,
+            BTreeMap::from([
+                ("LOG", "variadic"),
+                ("LOOP", "not an expression"),
+                ("STR", "uses `#` or `##`"),
+            ])
+        );
+        let identifiers = defines["NEXT"].identifiers();
+        assert_eq!(identifiers, vec![(None, "A")]);
+
+        let macros = defines
+            .clone()
+            .into_iter()
+            .filter(|(name, _)| !reasons.contains_key(name.as_str()))
+            .collect();
+        let original = [
+            "int f(int x, int y) {",
+            "  int m = MASK | N;",
+            "  return MAX(x + 1, y) + NEXT(f(x, y));",
+            "}",
+        ];
+        let expanded = "int f(int x, int y) {\n  int m = (1 | 2) | (1 << 4);\n  return ((x + 1) > (y) ? (x + 1) : (y)) + ((f(x, y)) + 1);\n}";
+        let uses: Vec<_> = macro_spans(&original, expanded, 0, &macros, &defines)
+            .into_iter()
+            .map(|u| {
+                let args: Vec<_> = u.args.iter().map(|a| &expanded[a.start..a.end]).collect();
+                (u.name, &expanded[u.span.start..u.span.end], args)
+            })
+            .collect();
+        assert_eq!(
+            uses,
+            vec![
+                ("MASK", "(1 | 2)", vec![]),
+                ("N", "(1 << 4)", vec![]),
+                ("MAX", "((x + 1) > (y) ? (x + 1) : (y))", vec!["x + 1", "y"]),
+                ("NEXT", "((f(x, y)) + 1)", vec!["f(x, y)"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        let source = "/*\n * Copyright (c) 2023\n */\n\n#include <stdio.h>\n\n/** The counter. */\nint x;\n// Adds one.\n// Returns the result.\nint f(int y) { return y + 1; }\nint z; /* unrelated */\nint w;\n";
+        assert_eq!(
+            header_comment(source).as_deref(),
+            Some("Copyright (c) 2023")
+        );
+        assert_eq!(leading_comment(source, 8).as_deref(), Some("The counter."));
+        assert_eq!(
+            leading_comment(source, 11).as_deref(),
+            Some("Adds one.\nReturns the result.")
+        );
+        assert_eq!(leading_comment(source, 13), None);
+        assert_eq!(leading_comment(source, 5), None);
+    }
+
+    #[test]
+    fn test_locations() {
+        let dir = std::env::temp_dir().join(format!("simcrat-locations-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("foo.c");
+        let original = "#define N 16
+#define M \\
+  2
+#define T int
+struct s { int a; };
+int x; int y;
+    T f(void) {
+  return 0;
+}
+";
+        fs::write(&path, original).unwrap();
+        let marker = |line: usize| format!("# {} \"{}\"\n", line, path.display());
+        let code = format!(
+            "{}#define N 16\n#define M 2\n{}#define T int\nstruct s {{ int a; }};\nint x; int y;\n  int f(void) {{\n  return 0;\n}}\n",
+            marker(1),
+            marker(4),
+        );
+        let program = parse(&code);
+        fs::remove_dir_all(dir).unwrap();
+
+        let position = |l: &Location| (l.line, l.column, l.end_line);
+        let location = program.term_location("f").unwrap();
+        assert_eq!(location.file, path);
+        // the column of the original code, where the return type is a macro
+        assert_eq!(position(location), (7, 5, 9));
+        let location = program.type_location(&CustomType::mk_struct("s")).unwrap();
+        assert_eq!(position(location), (5, 1, 5));
+        assert_eq!(position(program.term_location("x").unwrap()), (6, 1, 6));
+        assert_eq!(position(program.term_location("y").unwrap()), (6, 8, 6));
+        assert_eq!(position(program.term_location("N").unwrap()), (1, 1, 1));
+        assert_eq!(position(program.term_location("M").unwrap()), (2, 1, 3));
+    }
+
+    #[test]
+    fn test_anonymous_types() {
+        let program = parse(
+            "struct outer { union { int i; float f; } u; struct { int x; } s; };\n\
+             enum { A, B } x;\n\
+             enum { C, D };\n\
+             typedef struct { struct { int y; } inner; } wrapper;\n\
+             struct { int z; } g;\n",
+        );
+        let structs = program.structs();
+        let names: Vec<_> = structs.keys().copied().collect();
+        assert_eq!(
+            names,
+            vec!["g_type", "outer", "outer_s", "outer_u", "wrapper_inner"]
+        );
+        let deps: Vec<_> = structs["outer"]
+            .dependencies
+            .iter()
+            .map(|d| d.typ.name)
+            .collect();
+        assert_eq!(deps, vec!["outer_u", "outer_s"]);
+        let strct = &structs["outer_u"];
+        assert!(!strct.strct);
+        let code = program.struct_to_string(strct, vec![strct.tag("OuterU")]);
+        assert_eq!(code, "union OuterU { int i; float f; }");
+
+        let enums = program.enums();
+        let names: Vec<_> = enums.keys().copied().collect();
+        assert_eq!(names, vec!["C_enum", "x_type"]);
+        let enm = &enums["x_type"];
+        let code = program.enum_to_string(enm, vec![enm.tag("XType")]);
+        assert_eq!(code, "enum XType { A, B }");
+
+        let typedefs = program.typedefs();
+        let deps: Vec<_> = typedefs["wrapper"]
+            .dependencies
+            .iter()
+            .map(|d| d.typ.name)
+            .collect();
+        assert_eq!(deps, vec!["wrapper_inner"]);
+
+        let (variables, _) = program.variables();
+        let deps: Vec<_> = variables["x"]
+            .type_dependencies
+            .iter()
+            .map(|d| d.typ)
+            .collect();
+        assert_eq!(deps, vec![CustomType::mk_enum("x_type")]);
+    }
+
+    #[test]
+    fn test_anonymous_type_collisions() {
+        let program = parse(
+            "struct x_type { int a; };\n\
+             struct { int b; } x;\n\
+             typedef int outer_s;\n\
+             struct outer { struct { int c; } s; };\n\
+             union A_enum { int d; };\n\
+             enum { A, B };\n",
+        );
+        let structs = program.structs();
+        let names: Vec<_> = structs.keys().copied().collect();
+        assert_eq!(
+            names,
+            vec!["A_enum", "outer", "outer_s1", "x_type", "x_type1"]
+        );
+        assert!(structs["x_type1"].strct);
+        let (variables, _) = program.variables();
+        let deps: Vec<_> = variables["x"]
+            .type_dependencies
+            .iter()
+            .map(|d| d.typ)
+            .collect();
+        assert_eq!(deps, vec![CustomType::mk_struct("x_type1")]);
+
+        let enums = program.enums();
+        let names: Vec<_> = enums.keys().copied().collect();
+        assert_eq!(names, vec!["A_enum1"]);
+    }
+
+    #[test]
+    fn test_bit_fields() {
+        let program = parse(
+            "struct s { unsigned a : 1, b : 3; int c; signed char d : 4; unsigned char e : 5; \
+             int : 0; unsigned f : 31; _Bool g : 1; };\n\
+             struct t { int x; int y : 2, z; };\n",
+        );
+        let structs = program.structs();
+        assert!(structs["t"].bit_fields.is_none());
+        let bit_fields = structs["s"].bit_fields.as_ref().unwrap();
+        assert_eq!(bit_fields.units, vec![32, 8, 8, 32]);
+        let fields: Vec<_> = bit_fields
+            .fields
+            .iter()
+            .map(|f| (f.name.as_str(), f.unit, f.offset, f.width, f.signed))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("a", 0, 0, 1, false),
+                ("b", 0, 1, 3, false),
+                ("d", 1, 0, 4, true),
+                ("e", 2, 0, 5, false),
+                ("f", 3, 0, 31, false),
+                ("g", 3, 31, 1, false),
+            ]
+        );
+        assert!(bit_fields.fields[5].boolean);
+        let decls: Vec<_> = bit_fields
+            .backing_declarations()
+            .into_iter()
+            .map(|(_, d)| d)
+            .collect();
+        assert_eq!(
+            decls,
+            vec![
+                "uint32_t bitfield_0; /* a: bits 0..=0, b: bits 1..=3 */".to_string(),
+                "uint8_t bitfield_1; /* d: bits 0..=3 */ uint8_t bitfield_2; /* e: bits 0..=4 */ \
+                 uint32_t bitfield_3; /* f: bits 0..=30, g: bits 31..=31 */"
+                    .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_function_pointers() {
+        let program = parse(
+            "typedef int (*cmp_t)(const void *, const void *);
+struct ops { int (*apply)(int); cmp_t cmp; int n; };
+void sort(void *base, int n, cmp_t cmp);
+int by_value(const void *a, const void *b) { return 0; }
+int twice(int x) { return 2 * x; }
+int negate(int x) { return -x; }
+struct ops table = { negate, 0, 1 };
+int run(struct ops *o, int (*f)(int)) {
+    sort(0, 0, &by_value);
+    o->apply = twice;
+    return f(1) + o->apply(2) + table.apply(3);
+}",
+        );
+        let decls = &program.function_pointer_declarations;
+        assert_eq!(
+            decls["apply"].iter().collect::<Vec<_>>(),
+            vec!["int (*apply)(int);"]
+        );
+        assert!(decls.contains_key("cmp"));
+        assert!(!decls.contains_key("n"));
+        assert_eq!(program.function_pointer_fields["ops"].len(), 2);
+
+        let functions = program.functions();
+        let run = &functions["run"];
+        let uses: Vec<_> = run
+            .function_pointers
+            .iter()
+            .map(|u| (u.function.name, u.context.as_str()))
+            .collect();
+        assert_eq!(
+            uses,
+            vec![
+                ("by_value", "passed as argument 3 of `sort` in `run`"),
+                (
+                    "twice",
+                    "assigned to `o->apply` in `run`, declared as `int (*apply)(int);`"
+                ),
+            ]
+        );
+        assert_eq!(run.callees.len(), 1);
+        assert_eq!(run.indirect_calls, vec![1, 1, 1]);
+
+        let (variables, _) = program.variables();
+        let stored = &variables["table"].function_pointers;
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].function.name, "negate");
+        assert!(stored[0]
+            .context
+            .starts_with("stored in `struct ops table`"));
+    }
+
+    #[test]
+    fn test_enumerators() {
+        let program = parse(
+            "enum color { RED, GREEN = 3 };
+typedef enum { SMALL, LARGE } size;
+int largest = LARGE;
+int f(int c) { return c == GREEN ? SMALL : RED; }
+int g(void) { int RED = 1; return RED; }
+int h(int RED) { return RED; }",
+        );
+        assert_eq!(
+            program.enumerators["RED"],
+            (TypeSort::Enum, "color".to_string())
+        );
+        assert_eq!(
+            program.enumerators["SMALL"],
+            (TypeSort::Typedef, "size".to_string())
+        );
+
+        fn enumerators<'a>(deps: &[TypeDependency<'a>]) -> Vec<(&'a str, CustomType<'a>)> {
+            deps.iter()
+                .filter_map(|d| Some((d.enumerator?, d.typ)))
+                .collect()
+        }
+        let functions = program.functions();
+        assert_eq!(
+            enumerators(&functions["f"].type_dependencies),
+            vec![
+                ("GREEN", CustomType::mk_enum("color")),
+                ("SMALL", CustomType::mk_typedef("size")),
+                ("RED", CustomType::mk_enum("color")),
+            ]
+        );
+        assert!(enumerators(&functions["g"].type_dependencies).is_empty());
+        assert!(enumerators(&functions["h"].type_dependencies).is_empty());
+
+        let (variables, _) = program.variables();
+        assert_eq!(
+            enumerators(&variables["largest"].type_dependencies),
+            vec![("LARGE", CustomType::mk_typedef("size"))]
+        );
+    }
+
+    #[test]
+    fn test_collect_sources() {
+        let dir = std::env::temp_dir().join(format!("simcrat-sources-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        for file in ["main.c", "util.h", "lib/list.c", ".git/hook.c"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let mut files = vec![];
+        collect_sources(&dir, &mut files);
+        collect_sources(&dir.join("main.c"), &mut files);
+        collect_sources(&dir.join("util.h"), &mut files);
+        fs::remove_dir_all(&dir).unwrap();
+        let files: Vec<_> = files
+            .iter()
+            .map(|f| f.strip_prefix(&dir).unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(files, vec!["lib/list.c", "main.c", "util.h"]);
     }
 }
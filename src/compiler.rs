@@ -35,6 +35,7 @@ use rustc_span::{
     BytePos, Pos, Span, SpanData,
 };
 use rustfix::{LinePosition, LineRange, Replacement, Snippet, Solution, Suggestion};
+use serde::{Deserialize, Serialize};
 
 use crate::c_parser::TypeSort;
 
@@ -457,11 +458,14 @@ fn find_deps() -> Options {
     rustc_session::config::build_session_options(&matches)
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct FunTySig {
     pub params: Vec<Type>,
     pub ret: Type,
     pub generic: bool,
+    /// Whether the function takes variable arguments after `params`.
+    #[serde(default)]
+    pub variadic: bool,
 }
 
 impl FunTySig {
@@ -486,6 +490,7 @@ impl FunTySig {
             params,
             ret,
             generic,
+            variadic: decl.c_variadic,
         }
     }
 
@@ -494,6 +499,7 @@ impl FunTySig {
             params: self.params.into_iter().map(|t| t.into_c(map)).collect(),
             ret: self.ret.into_c(map),
             generic: self.generic,
+            variadic: self.variadic,
         }
     }
 
@@ -516,12 +522,17 @@ impl FunTySig {
 
 impl fmt::Display for FunTySig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt_list(f, self.params.iter(), "fn(", ", ", ")")?;
+        let end = match (self.variadic, self.params.is_empty()) {
+            (false, _) => ")",
+            (true, true) => "...)",
+            (true, false) => ", ...)",
+        };
+        fmt_list(f, self.params.iter(), "fn(", ", ", end)?;
         write!(f, " -> {}", self.ret)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct PathSeg {
     ident: String,
     args: Vec<Type>,
@@ -591,7 +602,7 @@ impl fmt::Display for PathSeg {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Type {
     Slice(Box<Type>),
     Array(Box<Type>, String),
@@ -864,7 +875,7 @@ fn fmt_list<T: fmt::Display, I: Iterator<Item = T>>(
     write!(f, "{}", end)
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParsedItem {
     pub name: String,
     pub code: String,
@@ -935,7 +946,7 @@ impl ParsedItem {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ItemSort {
     Type(TypeInfo),
     Variable(VariableInfo),
@@ -943,13 +954,15 @@ pub enum ItemSort {
     Use,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TypeInfo {
     pub sort: TypeSort,
     pub derives: BTreeSet<String>,
+    #[serde(default)]
+    pub variants: Vec<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VariableInfo {
     pub is_const: bool,
     pub is_mutable: bool,
@@ -957,7 +970,7 @@ pub struct VariableInfo {
     pub ty_str: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FunctionInfo {
     pub signature: String,
     pub signature_ty: FunTySig,
@@ -983,18 +996,26 @@ pub fn parse(code: &str) -> Option<Vec<ParsedItem>> {
                         ItemKind::TyAlias(_, _) => ItemSort::Type(TypeInfo {
                             sort: TypeSort::Typedef,
                             derives: BTreeSet::new(),
+                            variants: vec![],
                         }),
-                        ItemKind::Enum(_, _) => ItemSort::Type(TypeInfo {
+                        ItemKind::Enum(def, _) => ItemSort::Type(TypeInfo {
                             sort: TypeSort::Enum,
                             derives: BTreeSet::new(),
+                            variants: def
+                                .variants
+                                .iter()
+                                .map(|v| v.ident.name.to_ident_string())
+                                .collect(),
                         }),
                         ItemKind::Struct(_, _) => ItemSort::Type(TypeInfo {
                             sort: TypeSort::Struct,
                             derives: BTreeSet::new(),
+                            variants: vec![],
                         }),
                         ItemKind::Union(_, _) => ItemSort::Type(TypeInfo {
                             sort: TypeSort::Union,
                             derives: BTreeSet::new(),
+                            variants: vec![],
                         }),
                         ItemKind::Impl(i) => {
                             if !DERIVES.contains(&item_code.as_str()) {
@@ -1321,6 +1342,46 @@ pub fn rename_item(code: &str, new_name: &str) -> Option<String> {
     Some(rustfix::apply_suggestions(code, &suggestions).unwrap())
 }
 
+pub fn make_public(code: &str) -> Option<String> {
+    let full_code = format!("\n{}", code);
+    let config = make_config(&full_code);
+    let suggestions: Vec<_> = run_compiler(config, |compiler| {
+        compiler.enter(|queries| {
+            queries.global_ctxt().ok()?.enter(|tcx| {
+                let source_map = compiler.session().source_map();
+                let hir = tcx.hir();
+                let mut spans = vec![];
+                for id in hir.items() {
+                    if tcx.def_path(id.owner_id.to_def_id()).data.len() > 1 {
+                        continue;
+                    }
+                    let item = hir.item(id);
+                    match &item.kind {
+                        ItemKind::Struct(v, _) | ItemKind::Union(v, _) => {
+                            spans.push(item.vis_span);
+                            spans.extend(v.fields().iter().map(|f| f.vis_span));
+                        }
+                        ItemKind::Enum(_, _)
+                        | ItemKind::TyAlias(_, _)
+                        | ItemKind::Static(_, _, _)
+                        | ItemKind::Const(_, _)
+                        | ItemKind::Fn(_, _, _) => spans.push(item.vis_span),
+                        _ => (),
+                    }
+                }
+                let suggestions = spans
+                    .into_iter()
+                    .filter(|span| span.is_empty())
+                    .map(|span| make_suggestion(span_to_snippet(span, source_map), "pub "))
+                    .collect();
+                Some(suggestions)
+            })
+        })
+    })??;
+    let full_code = rustfix::apply_suggestions(&full_code, &suggestions).expect(code);
+    Some(full_code[1..].to_string())
+}
+
 pub fn add_trait_uses<'i, I: IntoIterator<Item = &'i String>>(
     code: &str,
     uses: I,
@@ -2332,6 +2393,26 @@ mod tests {
             "fn f() { std::fs::read(\"\"); }"
         );
     }
+
+    #[test]
+    fn test_public() {
+        assert_eq!(
+            make_public("struct S { x: i32, pub y: i32 }").unwrap(),
+            "pub struct S { pub x: i32, pub y: i32 }"
+        );
+        assert_eq!(
+            make_public("#[derive(Clone)]\nstruct T(i32);").unwrap(),
+            "#[derive(Clone)]\npub struct T(pub i32);"
+        );
+        assert_eq!(
+            make_public("static X: i32 = 0;\nfn f() { fn g() {} }").unwrap(),
+            "pub static X: i32 = 0;\npub fn f() { fn g() {} }"
+        );
+        assert_eq!(
+            make_public("use crate::*;\npub(crate) type U = usize;").unwrap(),
+            "use crate::*;\npub(crate) type U = usize;"
+        );
+    }
 }
 
 static C_TYPE_PREFIXES: [&str; 6] = [
@@ -0,0 +1,119 @@
+use std::{fs, path::Path};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::c_parser::{LibraryFilter, Preprocessor};
+
+pub const DEFAULT_CONFIG_FILE: &str = "simcrat.toml";
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Openai,
+    Local,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub log_file: Option<String>,
+    pub client: ClientConfig,
+    pub db: DbFileConfig,
+    pub translation: TranslationConfig,
+    pub preprocessor: Preprocessor,
+    pub library: LibraryFilter,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    pub backend: Option<Backend>,
+    pub model: Option<String>,
+    pub api_key_file: Option<String>,
+    pub concurrency: Option<usize>,
+    pub retries: Option<usize>,
+    pub local_url: Option<String>,
+    pub local_max_prompt_tokens: Option<usize>,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DbFileConfig {
+    pub name: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<String>,
+    pub password: Option<String>,
+    pub real_time: Option<bool>,
+    pub replay: Option<bool>,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TranslationConfig {
+    pub max_tokens: Option<usize>,
+    pub candidate: Option<bool>,
+    pub num_signatures: Option<usize>,
+    pub augmentation: Option<bool>,
+    pub fix: Option<bool>,
+    pub stage: Option<bool>,
+    pub max_fixes: Option<usize>,
+    pub macros: Option<bool>,
+    pub comments: Option<bool>,
+    pub provenance_comments: Option<bool>,
+}
+
+impl FileConfig {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let config = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let config: Self = toml::from_str(&config)
+            .map_err(|e| format!("invalid config {}: {}", path.display(), e))?;
+        if config.client.concurrency == Some(0) {
+            return Err(format!(
+                "invalid config {}: concurrency must be positive",
+                path.display()
+            ));
+        }
+        Ok(config)
+    }
+
+    /// Loads `path` if given, and `simcrat.toml` in the current directory if it exists otherwise.
+    pub fn load(path: Option<&str>) -> Result<Self, String> {
+        match path {
+            Some(path) => Self::from_file(path),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Self::from_file(DEFAULT_CONFIG_FILE),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_file() {
+        let dir = std::env::temp_dir().join(format!("simcrat-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("simcrat.toml");
+
+        fs::write(&path, "[client]\nconcurrency = 4\n").unwrap();
+        let config = FileConfig::from_file(&path).unwrap();
+        assert_eq!(config.client.concurrency, Some(4));
+
+        fs::write(&path, "[client]\nconcurrency = 0\n").unwrap();
+        let err = FileConfig::from_file(&path).unwrap_err();
+        assert!(err.contains("concurrency must be positive"), "{}", err);
+
+        fs::write(&path, "[client]\nconcurency = 4\n").unwrap();
+        let err = FileConfig::from_file(&path).unwrap_err();
+        assert!(err.starts_with("invalid config"), "{}", err);
+
+        let err = FileConfig::from_file(dir.join("missing.toml")).unwrap_err();
+        assert!(err.starts_with("cannot read"), "{}", err);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -127,6 +127,36 @@ pub fn compute_sccs<T: Clone + Eq + PartialOrd + Ord>(
     (component_graph, component_elems)
 }
 
+/// Adds the edges of `edges` that neither are self-loops nor close a cycle in `graph`.
+pub fn add_acyclic_edges<T: Clone + Eq + PartialOrd + Ord>(
+    graph: &mut BTreeMap<T, BTreeSet<T>>,
+    edges: impl IntoIterator<Item = (T, T)>,
+) {
+    for (from, to) in edges {
+        if from != to && !reachable(graph, &to, &from) {
+            graph.get_mut(&from).unwrap().insert(to);
+        }
+    }
+}
+
+fn reachable<T: Clone + Eq + PartialOrd + Ord>(
+    graph: &BTreeMap<T, BTreeSet<T>>,
+    from: &T,
+    to: &T,
+) -> bool {
+    let mut reached = BTreeSet::new();
+    let mut stack = vec![from];
+    while let Some(node) = stack.pop() {
+        if node == to {
+            return true;
+        }
+        if reached.insert(node) {
+            stack.extend(graph.get(node).into_iter().flatten());
+        }
+    }
+    false
+}
+
 #[derive(Clone, Copy, Eq, PartialEq, Debug, Hash, PartialOrd, Ord)]
 #[repr(transparent)]
 pub struct Id(usize);
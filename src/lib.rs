@@ -43,8 +43,10 @@ extern crate rustc_passes;
 extern crate rustc_session;
 extern crate rustc_span;
 
+pub mod bench;
 pub mod c_parser;
 pub mod compiler;
+pub mod config;
 pub mod graph;
 pub mod llm_client;
 pub mod translation;
@@ -45,6 +45,7 @@ where
 {
     collection: Option<Collection<CacheData<K, V>>>,
     real_time: bool,
+    replay: bool,
 }
 
 pub struct DbConfig {
@@ -53,6 +54,7 @@ pub struct DbConfig {
     pub port: Option<String>,
     pub password: Option<String>,
     pub real_time: bool,
+    pub replay: bool,
 }
 
 impl<K, V> Cache<K, V>
@@ -61,6 +63,10 @@ where
     V: Sync + Send + Unpin + Serialize + DeserializeOwned + HasElapsed,
 {
     pub fn new(conf: DbConfig) -> Self {
+        assert!(
+            !conf.replay || conf.name.is_some(),
+            "replay requires a cache database"
+        );
         let collection = if let Some(name) = conf.name {
             let host = conf.host.unwrap_or("localhost".to_string());
             let port = conf.port.unwrap_or("27017".to_string()).parse().ok();
@@ -84,9 +90,15 @@ where
         Self {
             collection,
             real_time: conf.real_time,
+            replay: conf.replay,
         }
     }
 
+    /// Whether a miss must be reported instead of sending the request.
+    pub fn is_replay(&self) -> bool {
+        self.replay
+    }
+
     pub async fn get(&self, key: &K) -> Option<V> {
         let collection = self.collection.as_ref()?;
         let data = collection
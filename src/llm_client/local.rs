@@ -2,7 +2,7 @@
 
 use std::{
     sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
+        atomic::{AtomicUsize, Ordering},
         Mutex,
     },
     time::{Duration, Instant},
@@ -15,7 +15,7 @@ use serde::{Deserialize, Serialize};
 
 use super::{
     cache::{Cache, DbConfig, HasElapsed},
-    tokens_in_str, LanguageModel,
+    record_miss, record_usage, tokens_in_str, CacheMiss, LanguageModel,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -71,47 +71,90 @@ pub struct LocalClient {
     inner: Client,
     url: String,
     cache: Cache<CacheKey, CacheVal>,
-    can_send_request: AtomicBool,
+    possible_requests: AtomicUsize,
+    max_prompt_tokens: usize,
+    dry_run: bool,
 
+    total_requests: AtomicUsize,
     total_request_tokens: AtomicUsize,
     total_response_tokens: AtomicUsize,
     total_response_time: Mutex<f32>,
+    misses: Mutex<Vec<CacheMiss>>,
 }
 
 impl LocalClient {
-    pub fn new(url: String, db_conf: DbConfig) -> Self {
+    pub fn new(
+        url: String,
+        concurrency: usize,
+        max_prompt_tokens: usize,
+        db_conf: DbConfig,
+    ) -> Self {
+        assert!(concurrency > 0);
         let inner = Client::new();
         let cache = Cache::new(db_conf);
         Self {
             inner,
             url,
             cache,
-            can_send_request: AtomicBool::new(true),
+            possible_requests: AtomicUsize::new(concurrency),
+            max_prompt_tokens,
+            dry_run: false,
+            total_requests: AtomicUsize::new(0),
             total_request_tokens: AtomicUsize::new(0),
             total_response_tokens: AtomicUsize::new(0),
             total_response_time: Mutex::new(0.0),
+            misses: Mutex::new(vec![]),
         }
     }
 
+    /// Counts the requests that would be sent without sending them, as if
+    /// each response used all the tokens it may generate. Cached responses
+    /// are still returned and not counted because they cost nothing, and
+    /// other requests get no response, as in a replay.
+    pub fn enable_dry_run(&mut self) {
+        self.dry_run = true;
+    }
+
     async fn send_request(
         &self,
+        kind: &str,
         prompt: &str,
         new_tokens_once: usize,
         stop: Option<&str>,
-    ) -> String {
-        if tokens_in_str(prompt) > 2047 {
+    ) -> Option<String> {
+        if tokens_in_str(prompt) > self.max_prompt_tokens && !self.dry_run {
             panic!("{}", prompt);
         }
 
         let key = CacheKey::new(prompt, new_tokens_once, &stop);
         let (result, hit) = if let Some(result) = self.cache.get(&key).await {
             (result, true)
+        } else if self.dry_run {
+            self.total_requests.fetch_add(1, Ordering::AcqRel);
+            self.total_request_tokens
+                .fetch_add(tokens_in_str(prompt), Ordering::AcqRel);
+            self.total_response_tokens
+                .fetch_add(new_tokens_once, Ordering::AcqRel);
+            return None;
+        } else if self.cache.is_replay() {
+            record_miss(&self.misses, kind);
+            return None;
         } else {
-            while self
-                .can_send_request
-                .compare_exchange(true, false, Ordering::AcqRel, Ordering::Acquire)
-                .is_err()
-            {
+            loop {
+                let possible = self.possible_requests.load(Ordering::Relaxed);
+                if possible > 0
+                    && self
+                        .possible_requests
+                        .compare_exchange(
+                            possible,
+                            possible - 1,
+                            Ordering::AcqRel,
+                            Ordering::Acquire,
+                        )
+                        .is_ok()
+                {
+                    break;
+                }
                 tokio::time::sleep(Duration::from_secs(1)).await;
             }
 
@@ -121,7 +164,7 @@ impl LocalClient {
             let elapsed = now.elapsed().as_secs_f32();
             tracing::info!("send_request DONE ({} seconds)", elapsed);
 
-            self.can_send_request.store(true, Ordering::Release);
+            self.possible_requests.fetch_add(1, Ordering::AcqRel);
 
             let res: GenerationResult = res.expect(prompt).json().await.expect(prompt);
             let response = res.result.expect(prompt);
@@ -146,14 +189,21 @@ cache {}
             result.content
         );
 
+        if hit && self.dry_run {
+            return Some(result.content);
+        }
+        if !hit {
+            self.total_requests.fetch_add(1, Ordering::AcqRel);
+        }
         self.total_request_tokens
             .fetch_add(result.request_tokens, Ordering::AcqRel);
         self.total_response_tokens
             .fetch_add(result.response_tokens, Ordering::AcqRel);
+        record_usage(result.request_tokens, result.response_tokens);
         let mut time = self.total_response_time.lock().unwrap();
         *time += result.elapsed;
 
-        result.content
+        Some(result.content)
     }
 }
 
@@ -161,6 +211,10 @@ const HEADER: &str = "You are an excellent systems programmer skilled in both C
 
 #[async_trait]
 impl LanguageModel for LocalClient {
+    fn requests(&self) -> usize {
+        self.total_requests.load(Ordering::Acquire)
+    }
+
     fn request_tokens(&self) -> usize {
         self.total_request_tokens.load(Ordering::Acquire)
     }
@@ -173,6 +227,10 @@ impl LanguageModel for LocalClient {
         *self.total_response_time.lock().unwrap()
     }
 
+    fn cache_misses(&self) -> Vec<CacheMiss> {
+        self.misses.lock().unwrap().clone()
+    }
+
     async fn rename_type(&self, name: &str) -> String {
         if name.chars().next().unwrap().is_uppercase()
             && !name.contains('_')
@@ -193,9 +251,11 @@ impl LanguageModel for LocalClient {
                 (convert(name), "`".to_string()),
             ],
         );
-        let res = self
-            .send_request(&prompt, tokens_in_str(name) * 2, Some("`"))
-            .await;
+        let res = some_or!(
+            self.send_request("rename_type", &prompt, tokens_in_str(name) * 2, Some("`"))
+                .await,
+            return name.to_string()
+        );
         let i = res.find('`').unwrap();
         res[..i].to_string()
     }
@@ -250,8 +310,13 @@ Try to avoid unsafe code.",
             ],
         );
         let res = self
-            .send_request(&prompt, tokens_in_str(code) * 2, Some("```"))
-            .await;
+            .send_request(
+                "translate_type",
+                &prompt,
+                tokens_in_str(code) * 2,
+                Some("```"),
+            )
+            .await?;
         let i = res.find("```")?;
         Some(res[..i].to_string())
     }
@@ -273,9 +338,16 @@ Try to avoid unsafe code.",
                 (convert(name), "`".to_string()),
             ],
         );
-        let res = self
-            .send_request(&prompt, tokens_in_str(name) * 2, Some("`"))
-            .await;
+        let res = some_or!(
+            self.send_request(
+                "rename_variable",
+                &prompt,
+                tokens_in_str(name) * 2,
+                Some("`")
+            )
+            .await,
+            return name.to_string()
+        );
         let i = res.find('`').unwrap();
         res[..i].to_string()
     }
@@ -316,8 +388,72 @@ Try to avoid unsafe code.",
             ],
         );
         let res = self
-            .send_request(&prompt, tokens_in_str(code) * 2, Some("```"))
-            .await;
+            .send_request(
+                "translate_variable",
+                &prompt,
+                tokens_in_str(code) * 2,
+                Some("```"),
+            )
+            .await?;
+        let i = res.find("```")?;
+        Some(res[..i].to_string())
+    }
+
+    async fn translate_macro(
+        &self,
+        code: &str,
+        sort: &str,
+        new_name: &str,
+        deps: &[String],
+    ) -> Option<String> {
+        fn task(code: &str, sort: &str, new_name: &str, deps: &[String]) -> String {
+            format!(
+                "{}Translate the following C macro to a Rust {} named `{}`:
+```c
+{}
+```
+Try to avoid unsafe code.",
+                make_deps(deps),
+                sort,
+                new_name,
+                code
+            )
+        }
+        fn answer(code: &str, close: bool) -> String {
+            format!(
+                "This is the equivalent Rust definition:
+```rust
+{}{}",
+                code,
+                if close { "\n```" } else { "" }
+            )
+        }
+        let prompt = make_prompt(
+            Some(HEADER),
+            &[
+                (
+                    task("#define BUF_SIZE 1024", "constant", "BUF_SIZE", &[]),
+                    answer("const BUF_SIZE: usize = 1024;", true),
+                ),
+                (
+                    task("#define GREETING \"hello\"", "constant", "GREETING", &[]),
+                    answer("const GREETING: &str = \"hello\";", true),
+                ),
+                (
+                    task("#define SQUARE(x) ((x) * (x))", "function", "square", &[]),
+                    answer("fn square(x: i32) -> i32 {\n    x * x\n}", true),
+                ),
+                (task(code, sort, new_name, deps), answer("", false)),
+            ],
+        );
+        let res = self
+            .send_request(
+                "translate_macro",
+                &prompt,
+                tokens_in_str(code) * 4,
+                Some("```"),
+            )
+            .await?;
         let i = res.find("```")?;
         Some(res[..i].to_string())
     }
@@ -339,9 +475,16 @@ Try to avoid unsafe code.",
                 (convert(name), "`".to_string()),
             ],
         );
-        let res = self
-            .send_request(&prompt, tokens_in_str(name) * 2, Some("`"))
-            .await;
+        let res = some_or!(
+            self.send_request(
+                "rename_function",
+                &prompt,
+                tokens_in_str(name) * 2,
+                Some("`")
+            )
+            .await,
+            return name.to_string()
+        );
         let i = res.find('`').unwrap();
         res[..i].to_string()
     }
@@ -423,7 +566,11 @@ Give {} Rust-idiomatic candidate signatures.",
                     (task(code, deps, n), answer(sigs_str.as_str())),
                 ],
             );
-            let res = self.send_request(&prompt, 32, Some("`")).await;
+            let res = some_or!(
+                self.send_request("translate_signature", &prompt, 32, Some("`"))
+                    .await,
+                break
+            );
             let i = res.find('`').unwrap();
             let sig = res[..i].to_string();
             if let Some(sig) = sig.strip_suffix(';') {
@@ -509,8 +656,13 @@ Try to avoid unsafe code.",
             ],
         );
         let res = self
-            .send_request(&prompt, tokens_in_str(code) * 2, Some("```"))
-            .await;
+            .send_request(
+                "translate_function",
+                &prompt,
+                tokens_in_str(code) * 2,
+                Some("```"),
+            )
+            .await?;
         let i = res.find("```")?;
         start += &res[..i];
         Some(start)
@@ -541,8 +693,8 @@ Write the fixed code.",
         );
         let prompt = make_prompt(Some(HEADER), &[(task, answer)]);
         let res = self
-            .send_request(&prompt, tokens_in_str(code) * 2, Some("```"))
-            .await;
+            .send_request("fix", &prompt, tokens_in_str(code) * 2, Some("```"))
+            .await?;
         let i = res.find("```")?;
         start += &res[..i];
         Some(start)
@@ -595,7 +747,10 @@ Which one is more Rust-idiomatic?",
                 (task(code1, code2), answer("", false)),
             ],
         );
-        let res = self.send_request(&prompt, 2, Some("`")).await;
+        let res = some_or!(
+            self.send_request("compare", &prompt, 2, Some("`")).await,
+            return std::cmp::Ordering::Equal
+        );
         let c = some_or!(
             res.chars().find(|&c| c == '1' || c == '2'),
             return std::cmp::Ordering::Equal
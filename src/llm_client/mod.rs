@@ -1,5 +1,16 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
 use async_trait::async_trait;
 use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 
 pub mod cache;
 pub mod local;
@@ -13,16 +24,129 @@ pub fn tokens_in_str(s: &str) -> usize {
     BPE.encode_with_special_tokens(s).len()
 }
 
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub request_tokens: usize,
+    pub response_tokens: usize,
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.request_tokens += other.request_tokens;
+        self.response_tokens += other.response_tokens;
+    }
+}
+
+#[derive(Default)]
+struct UsageCounter {
+    request_tokens: AtomicUsize,
+    response_tokens: AtomicUsize,
+}
+
+tokio::task_local! {
+    static USAGE: Arc<UsageCounter>;
+}
+
+/// Runs `f` and returns the tokens of every request sent while polling it.
+pub async fn track_usage<F: Future>(f: F) -> (F::Output, TokenUsage) {
+    let counter = Arc::new(UsageCounter::default());
+    let output = USAGE.scope(counter.clone(), f).await;
+    let usage = TokenUsage {
+        request_tokens: counter.request_tokens.load(Ordering::Acquire),
+        response_tokens: counter.response_tokens.load(Ordering::Acquire),
+    };
+    (output, usage)
+}
+
+fn record_usage(request_tokens: usize, response_tokens: usize) {
+    let _ = USAGE.try_with(|counter| {
+        counter
+            .request_tokens
+            .fetch_add(request_tokens, Ordering::AcqRel);
+        counter
+            .response_tokens
+            .fetch_add(response_tokens, Ordering::AcqRel);
+    });
+}
+
+/// A request not found in the cache during a replay.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheMiss {
+    pub kind: String,
+    pub item: String,
+}
+
+tokio::task_local! {
+    static ITEM: String;
+}
+
+/// Runs `f` and attributes every request sent while polling it to `item`.
+pub async fn with_item<F: Future>(item: String, f: F) -> F::Output {
+    ITEM.scope(item, f).await
+}
+
+fn record_miss(misses: &Mutex<Vec<CacheMiss>>, kind: &str) {
+    let item = ITEM.try_with(|item| item.clone()).unwrap_or_default();
+    eprintln!("cache miss: {} of `{}`", kind, item);
+    let miss = CacheMiss {
+        kind: kind.to_string(),
+        item,
+    };
+    misses.lock().unwrap().push(miss);
+}
+
+/// USD per 1K tokens.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Price {
+    pub request: f64,
+    pub response: f64,
+}
+
+pub fn price_table(path: Option<&str>) -> BTreeMap<String, Price> {
+    let mut table: BTreeMap<_, _> = PRICES
+        .iter()
+        .map(|(model, request, response)| {
+            let price = Price {
+                request: *request,
+                response: *response,
+            };
+            (model.to_string(), price)
+        })
+        .collect();
+    if let Some(path) = path {
+        let prices: BTreeMap<String, Price> =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        table.extend(prices);
+    }
+    table
+}
+
+static PRICES: [(&str, f64, f64); 4] = [
+    ("gpt-3.5-turbo", 0.0015, 0.002),
+    ("gpt-3.5-turbo-16k", 0.003, 0.004),
+    ("gpt-4", 0.03, 0.06),
+    ("gpt-4-32k", 0.06, 0.12),
+];
+
 #[async_trait]
 pub trait LanguageModel {
+    fn requests(&self) -> usize;
     fn request_tokens(&self) -> usize;
     fn response_tokens(&self) -> usize;
     fn response_time(&self) -> f32;
+    fn cache_misses(&self) -> Vec<CacheMiss>;
 
     async fn rename_type(&self, name: &str) -> String;
     async fn translate_type(&self, code: &str, sort: &str, deps: &[String]) -> Option<String>;
     async fn rename_variable(&self, name: &str) -> String;
     async fn translate_variable(&self, code: &str, deps: &[String]) -> Option<String>;
+    async fn translate_macro(
+        &self,
+        code: &str,
+        sort: &str,
+        new_name: &str,
+        deps: &[String],
+    ) -> Option<String>;
     async fn rename_function(&self, name: &str) -> String;
     async fn translate_signature(
         &self,
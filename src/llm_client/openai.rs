@@ -14,7 +14,7 @@ use serde::{Deserialize, Serialize};
 
 use super::{
     cache::{Cache, DbConfig, HasElapsed},
-    tokens_in_str, LanguageModel,
+    record_miss, record_usage, tokens_in_str, CacheMiss, LanguageModel,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -80,14 +80,25 @@ pub struct OpenAIClient {
     model: Option<String>,
     cache: Cache<CacheKey, CacheVal>,
     possible_requests: AtomicUsize,
+    retries: usize,
+    dry_run: bool,
 
+    total_requests: AtomicUsize,
     total_request_tokens: AtomicUsize,
     total_response_tokens: AtomicUsize,
     total_response_time: Mutex<f32>,
+    misses: Mutex<Vec<CacheMiss>>,
 }
 
 impl OpenAIClient {
-    pub fn new(model: Option<String>, api_key_file: Option<String>, db_conf: DbConfig) -> Self {
+    pub fn new(
+        model: Option<String>,
+        api_key_file: Option<String>,
+        concurrency: usize,
+        retries: usize,
+        db_conf: DbConfig,
+    ) -> Self {
+        assert!(concurrency > 0);
         let inner = api_key_file.map(|api_key_file| {
             let api_key = fs::read_to_string(api_key_file).unwrap().trim().to_string();
             Client::new().with_api_key(api_key)
@@ -97,18 +108,31 @@ impl OpenAIClient {
             inner,
             model,
             cache,
-            possible_requests: AtomicUsize::new(30),
+            possible_requests: AtomicUsize::new(concurrency),
+            retries,
+            dry_run: false,
+            total_requests: AtomicUsize::new(0),
             total_request_tokens: AtomicUsize::new(0),
             total_response_tokens: AtomicUsize::new(0),
             total_response_time: Mutex::new(0.0),
+            misses: Mutex::new(vec![]),
         }
     }
 
+    /// Counts the requests that would be sent without sending them, as if
+    /// each response were as long as the code it asks for. Cached responses
+    /// are still returned and not counted because they cost nothing, and
+    /// other requests get no response, as in a replay.
+    pub fn enable_dry_run(&mut self) {
+        self.dry_run = true;
+    }
+
     async fn send_request(
         &self,
+        kind: &str,
         mut msgs: Vec<ChatCompletionRequestMessage>,
         stop: Option<&str>,
-    ) -> String {
+    ) -> Option<String> {
         let msgs_str = msgs
             .iter()
             .map(|msg| format!("{}: {}", msg.role, msg.content))
@@ -118,6 +142,17 @@ impl OpenAIClient {
         let key = CacheKey::new(&msgs, &stop);
         let (result, hit) = if let Some(result) = self.cache.get(&key).await {
             (result, true)
+        } else if self.dry_run {
+            self.total_requests.fetch_add(1, Ordering::AcqRel);
+            self.total_request_tokens
+                .fetch_add(num_tokens(&msgs), Ordering::AcqRel);
+            let prompt = &msgs.last().unwrap().content;
+            self.total_response_tokens
+                .fetch_add(requested_tokens(prompt), Ordering::AcqRel);
+            return None;
+        } else if self.cache.is_replay() {
+            record_miss(&self.misses, kind);
+            return None;
         } else {
             let inner = self.inner.as_ref().expect(&msgs_str);
             let model = self.model.as_ref().expect(&msgs_str);
@@ -125,7 +160,7 @@ impl OpenAIClient {
 
             tracing::info!("send_request START");
             let (mut response, elapsed) = loop {
-                assert!(i < 10, "{:?}", msgs);
+                assert!(i < self.retries, "{:?}", msgs);
                 let mut request = CreateChatCompletionRequestArgs::default();
                 request
                     .model(model)
@@ -177,7 +212,7 @@ impl OpenAIClient {
                             elapsed,
                             err
                         );
-                        if i == 9 {
+                        if i == self.retries - 1 {
                             if let OpenAIError::ApiError(err) = err {
                                 if err.message == ERROR_MESSAGE {
                                     let response = CreateChatCompletionResponse {
@@ -244,19 +279,30 @@ cache {}
             result.content
         );
 
+        if hit && self.dry_run {
+            return Some(result.content);
+        }
+        if !hit {
+            self.total_requests.fetch_add(1, Ordering::AcqRel);
+        }
         self.total_request_tokens
             .fetch_add(result.request_tokens, Ordering::AcqRel);
         self.total_response_tokens
             .fetch_add(result.response_tokens, Ordering::AcqRel);
+        record_usage(result.request_tokens, result.response_tokens);
         let mut time = self.total_response_time.lock().unwrap();
         *time += result.elapsed;
 
-        result.content
+        Some(result.content)
     }
 }
 
 #[async_trait]
 impl LanguageModel for OpenAIClient {
+    fn requests(&self) -> usize {
+        self.total_requests.load(Ordering::Acquire)
+    }
+
     fn request_tokens(&self) -> usize {
         self.total_request_tokens.load(Ordering::Acquire)
     }
@@ -269,6 +315,10 @@ impl LanguageModel for OpenAIClient {
         *self.total_response_time.lock().unwrap()
     }
 
+    fn cache_misses(&self) -> Vec<CacheMiss> {
+        self.misses.lock().unwrap().clone()
+    }
+
     async fn rename_type(&self, name: &str) -> String {
         if name.chars().next().unwrap().is_uppercase()
             && !name.contains('_')
@@ -288,7 +338,10 @@ impl LanguageModel for OpenAIClient {
         let prompt = format!("Convert `{}` to `CamelCase`.", name);
         let m10 = user(&prompt);
         let msgs = vec![m1, m2, m3, m4, m5, m6, m7, m8, m9, m10];
-        let result = self.send_request(msgs, None).await;
+        let result = some_or!(
+            self.send_request("rename_type", msgs, None).await,
+            return name.to_string()
+        );
         extract_name(result)
     }
 
@@ -305,7 +358,7 @@ Try to avoid unsafe code.",
         );
         let m2 = user(&prompt);
         let msgs = vec![m1, m2];
-        let result = self.send_request(msgs, None).await;
+        let result = self.send_request("translate_type", msgs, None).await?;
         extract_code(&result, &["type ", "struct ", "union ", "enum "])
     }
 
@@ -325,7 +378,10 @@ Try to avoid unsafe code.",
         let prompt = format!("Convert `{}` to `SCREAMING_SNAKE_CASE`.", name);
         let m10 = user(&prompt);
         let msgs = vec![m1, m2, m3, m4, m5, m6, m7, m8, m9, m10];
-        let result = self.send_request(msgs, None).await;
+        let result = some_or!(
+            self.send_request("rename_variable", msgs, None).await,
+            return name.to_string()
+        );
         extract_name(result).replace(' ', "_")
     }
 
@@ -342,10 +398,33 @@ Try to avoid unsafe code.",
         );
         let m2 = user(&prompt);
         let msgs = vec![m1, m2];
-        let result = self.send_request(msgs, None).await;
+        let result = self.send_request("translate_variable", msgs, None).await?;
         extract_code(&result, &["const ", "static "])
     }
 
+    async fn translate_macro(
+        &self,
+        code: &str,
+        sort: &str,
+        new_name: &str,
+        deps: &[String],
+    ) -> Option<String> {
+        let m1 = system("You are a helpful assistant that translates C to Rust.");
+        let deps = make_deps(deps);
+        let prompt = format!(
+            "{}Translate the following C macro to a Rust {} named `{}` without any explanation:
+```
+{}
+```
+Try to avoid unsafe code.",
+            deps, sort, new_name, code
+        );
+        let m2 = user(&prompt);
+        let msgs = vec![m1, m2];
+        let result = self.send_request("translate_macro", msgs, None).await?;
+        extract_code(&result, &["const ", "static ", "fn ", "unsafe fn "])
+    }
+
     async fn rename_function(&self, name: &str) -> String {
         if !name.contains(|c: char| c.is_uppercase()) {
             return name.to_string();
@@ -362,7 +441,10 @@ Try to avoid unsafe code.",
         let prompt = format!("Convert `{}` to `snake_case`.", name);
         let m10 = user(&prompt);
         let msgs = vec![m1, m2, m3, m4, m5, m6, m7, m8, m9, m10];
-        let result = self.send_request(msgs, None).await;
+        let result = some_or!(
+            self.send_request("rename_function", msgs, None).await,
+            return name.to_string()
+        );
         extract_name(result)
     }
 
@@ -423,7 +505,10 @@ Signatures:
         );
         let m6 = user(&signature_prompt(code, new_name, deps, n));
         let msgs = vec![m1, m2, m3, m4, m5, m6];
-        let result = self.send_request(msgs, None).await;
+        let result = some_or!(
+            self.send_request("translate_signature", msgs, None).await,
+            return vec![]
+        );
         let sigs: Vec<_> = result
             .lines()
             .filter_map(|s| {
@@ -506,7 +591,7 @@ Signatures:
         );
         let m2 = user(&prompt);
         let msgs = vec![m1, m2];
-        let result = self.send_request(msgs, None).await;
+        let result = self.send_request("translate_function", msgs, None).await?;
         let result = extract_code(&result, &["fn ", "unsafe fn "]).or_else(|| {
             if result.starts_with("fn ") | result.starts_with("unsafe fn ") {
                 Some(result)
@@ -549,7 +634,7 @@ The error message is:
         );
         let m2 = user(&prompt);
         let msgs = vec![m1, m2];
-        let result = self.send_request(msgs, None).await;
+        let result = self.send_request("fix", msgs, None).await?;
         extract_code(
             &result,
             &[
@@ -613,7 +698,10 @@ Choice: Implementation [n]",
         );
         let m4 = user(&prompt);
         let msgs = vec![m1, m2, m3, m4];
-        let result = self.send_request(msgs, None).await;
+        let result = some_or!(
+            self.send_request("compare", msgs, None).await,
+            return std::cmp::Ordering::Equal
+        );
         let s = "Choice: Implementation ";
         let i = some_or!(result.find(s), return std::cmp::Ordering::Equal);
         let c = some_or!(
@@ -717,7 +805,6 @@ fn role_to_str(role: &Role) -> &'static str {
     }
 }
 
-#[allow(unused)]
 fn num_tokens(msgs: &[ChatCompletionRequestMessage]) -> usize {
     msgs.iter()
         .map(|msg| 4 + tokens_in_str(role_to_str(&msg.role)) + tokens_in_str(&msg.content))
@@ -725,6 +812,12 @@ fn num_tokens(msgs: &[ChatCompletionRequestMessage]) -> usize {
         + 3
 }
 
+/// Approximates the tokens of the response to `prompt` by its last code
+/// block, or by the whole prompt if it has none.
+fn requested_tokens(prompt: &str) -> usize {
+    tokens_in_str(prompt.rsplit("```").nth(1).unwrap_or(prompt))
+}
+
 fn system(s: &str) -> ChatCompletionRequestMessage {
     ChatCompletionRequestMessage {
         role: Role::System,
@@ -1,5 +1,8 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
+    fs,
+    future::Future,
+    path::{Path, PathBuf},
     sync::RwLock,
 };
 
@@ -10,16 +13,17 @@ use lang_c::{
     span::{Node, Span},
 };
 use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     c_parser::{
-        self, CustomType, Enum, Function, Program, Struct, TypeDependency, TypeSort, Typedef,
-        Variable,
+        self, glob_match, BitFields, CustomType, Diagnostic, Enum, Function, Location, Macro,
+        Program, Struct, TermDependency, TypeDependency, TypeSort, Typedef, Variable,
     },
     compiler::{self, FunTySig, FunctionInfo, ItemSort, ParsedItem, TypeCheckingResult},
     graph,
     graph::Id,
-    llm_client::{tokens_in_str, LanguageModel},
+    llm_client::{self, tokens_in_str, CacheMiss, LanguageModel, Price, TokenUsage},
 };
 
 #[derive(Clone, Copy, Debug)]
@@ -30,9 +34,225 @@ pub struct Config {
     pub provide_signatures: bool,
     pub fix_errors: bool,
     pub consider_stages: bool,
+    pub max_fixes: usize,
+    pub recover_macros: bool,
+    pub prompt_comments: bool,
+    pub provenance_comments: bool,
     pub quiet: bool,
 }
 
+#[derive(Debug, Default, Clone)]
+pub struct Selection {
+    pub functions: Vec<String>,
+    pub files: Vec<String>,
+    pub types: Vec<String>,
+}
+
+impl Selection {
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty() && self.files.is_empty() && self.types.is_empty()
+    }
+
+    fn matches_file(&self, path: &str) -> bool {
+        let file_name = Path::new(path)
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or(path);
+        self.files
+            .iter()
+            .any(|p| glob_match(p, path) || glob_match(p, file_name))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PhaseEstimate {
+    pub requests: usize,
+    pub request_tokens: usize,
+    pub response_tokens: usize,
+}
+
+impl PhaseEstimate {
+    /// The requests counted after `before`, where both are running totals.
+    fn since(self, before: Self) -> Self {
+        Self {
+            requests: self.requests - before.requests,
+            request_tokens: self.request_tokens - before.request_tokens,
+            response_tokens: self.response_tokens - before.response_tokens,
+        }
+    }
+
+    pub fn cost(&self, price: &Price) -> f64 {
+        (self.request_tokens as f64 * price.request + self.response_tokens as f64 * price.response)
+            / 1000.0
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Estimate {
+    pub names: PhaseEstimate,
+    pub types: PhaseEstimate,
+    pub variables: PhaseEstimate,
+    pub protos: PhaseEstimate,
+    pub macros: PhaseEstimate,
+    pub functions: PhaseEstimate,
+    pub too_long: usize,
+}
+
+impl Estimate {
+    pub fn phases(&self) -> [(&'static str, PhaseEstimate); 6] {
+        [
+            ("names", self.names),
+            ("types", self.types),
+            ("variables", self.variables),
+            ("protos", self.protos),
+            ("macros", self.macros),
+            ("functions", self.functions),
+        ]
+    }
+
+    pub fn total(&self) -> PhaseEstimate {
+        let mut total = PhaseEstimate::default();
+        for (_, phase) in self.phases() {
+            total.requests += phase.requests;
+            total.request_tokens += phase.request_tokens;
+            total.response_tokens += phase.response_tokens;
+        }
+        total
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub items: Vec<ItemReport>,
+    pub program_size: ProgramSize,
+    pub errors: ErrorReport,
+    pub usage: UsageReport,
+    pub types: TypeStats,
+    pub stages: BTreeMap<usize, StageStats>,
+    pub cache_misses: Vec<CacheMiss>,
+    pub headers: HeaderReport,
+    pub skipped_files: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HeaderReport {
+    pub library: Vec<String>,
+    pub project: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ItemReport {
+    pub c_name: String,
+    pub rust_name: String,
+    pub kind: &'static str,
+    pub path: String,
+    pub stage: usize,
+    pub errors: usize,
+    pub failed: bool,
+    pub too_long: bool,
+    pub proto: bool,
+    pub types: Vec<String>,
+    pub request_tokens: usize,
+    pub response_tokens: usize,
+}
+
+impl ItemReport {
+    fn new(
+        c_name: &str,
+        rust_name: String,
+        kind: &'static str,
+        path: &str,
+        translated: &TranslationResult,
+    ) -> Self {
+        Self {
+            c_name: c_name.to_string(),
+            rust_name,
+            kind,
+            path: path.to_string(),
+            stage: translated.stage,
+            errors: translated.errors,
+            failed: translated.failed,
+            too_long: translated.too_long,
+            proto: translated.proto,
+            types: translated.types.clone(),
+            request_tokens: translated.usage.request_tokens,
+            response_tokens: translated.usage.response_tokens,
+        }
+    }
+}
+
+/// Where a translated item comes from in the C program.
+#[derive(Debug, Serialize)]
+pub struct Provenance {
+    pub c_name: String,
+    pub rust_name: String,
+    pub kind: &'static str,
+    pub path: String,
+    pub location: Option<Location>,
+    pub rust_items: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ProgramSize {
+    pub lines: usize,
+    pub types: usize,
+    pub variables: usize,
+    pub protos: usize,
+    pub functions: usize,
+    pub calls: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorReport {
+    pub variables: ErrorStats,
+    pub functions: ErrorStats,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ErrorStats {
+    pub errors: usize,
+    pub protos: usize,
+    pub longs: usize,
+    pub fails: usize,
+    pub w_errors: usize,
+    pub wo_errors: usize,
+    pub no_trans_errors: usize,
+    pub wo_error_names: Vec<String>,
+    pub no_trans_error_names: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct UsageReport {
+    pub request_tokens: usize,
+    pub response_tokens: usize,
+    pub response_time: f32,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TypeStats {
+    pub fulls: usize,
+    pub partials: usize,
+    pub uns: usize,
+    pub rust_types: BTreeMap<String, usize>,
+    pub c_types: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct StageStats {
+    pub definitions: usize,
+    pub errors: usize,
+}
+
+#[derive(Debug, Default)]
+struct SelectedItems<'ast> {
+    types: BTreeSet<CustomType<'ast>>,
+    variables: BTreeSet<&'ast str>,
+    protos: BTreeSet<&'ast str>,
+    functions: BTreeSet<&'ast str>,
+    stubs: BTreeSet<&'ast str>,
+    macros: BTreeSet<&'ast str>,
+}
+
 pub struct Translator<'ast> {
     program: &'ast Program,
     typedefs: BTreeMap<&'ast str, Typedef<'ast>>,
@@ -41,6 +261,7 @@ pub struct Translator<'ast> {
     variables: BTreeMap<&'ast str, Variable<'ast>>,
     protos: BTreeMap<&'ast str, Variable<'ast>>,
     functions: BTreeMap<&'ast str, Function<'ast>>,
+    macros: BTreeMap<&'ast str, &'ast Macro>,
 
     custom_types: Vec<CustomType<'ast>>,
     transitive_types: BTreeMap<CustomType<'ast>, BTreeSet<CustomType<'ast>>>,
@@ -55,16 +276,28 @@ pub struct Translator<'ast> {
     variable_elem_map: BTreeMap<Id, BTreeSet<&'ast str>>,
     function_graph: BTreeMap<Id, BTreeSet<Id>>,
     function_elem_map: BTreeMap<Id, BTreeSet<&'ast str>>,
+    /// Callees and functions used as function pointers, which are translated before the caller.
+    function_edges: BTreeMap<&'ast str, BTreeSet<&'ast str>>,
     called_functions: BTreeSet<&'ast str>,
+    /// How each function is used as a function pointer.
+    function_pointer_contexts: BTreeMap<&'ast str, BTreeSet<String>>,
 
     client: Box<dyn LanguageModel + Send + Sync>,
 
     new_type_names: BTreeMap<CustomType<'ast>, String>,
     new_term_names: BTreeMap<&'ast str, String>,
+    /// Paths of the Rust enum variants of the enumerators, e.g., `Color::Red` for `RED`.
+    new_enumerator_names: BTreeMap<&'ast str, String>,
 
     inner: RwLock<TranslatorInner<'ast>>,
 
+    /// `PREAMBLE` followed by `VA_ARG` if the program has variadic functions or calls through
+    /// pointers to them.
+    preamble: String,
+
     config: Config,
+    checkpoint_dir: Option<PathBuf>,
+    selected: Option<SelectedItems<'ast>>,
 }
 
 #[derive(Default)]
@@ -75,6 +308,7 @@ pub struct TranslatorInner<'ast> {
     translated_types: BTreeMap<CustomType<'ast>, TranslationResult>,
     translated_variables: BTreeMap<&'ast str, TranslationResult>,
     translated_functions: BTreeMap<&'ast str, TranslationResult>,
+    translated_macros: BTreeMap<&'ast str, TranslationResult>,
 }
 
 impl<'ast> TranslatorInner<'ast> {
@@ -106,14 +340,18 @@ impl<'ast> TranslatorInner<'ast> {
         types
             .iter()
             .flat_map(|x| self.translated_types.get(x))
-            .chain(vars.iter().flat_map(|x| self.translated_variables.get(x)))
+            .chain(vars.iter().flat_map(|x| {
+                self.translated_variables
+                    .get(x)
+                    .or_else(|| self.translated_macros.get(x))
+            }))
             .chain(funcs.iter().flat_map(|x| self.translated_functions.get(x)))
             .flat_map(|x| &x.items)
             .collect()
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct TranslationResult {
     items: Vec<ParsedItem>,
     stage: usize,
@@ -122,6 +360,8 @@ struct TranslationResult {
     failed: bool,
     proto: bool,
     types: Vec<String>,
+    #[serde(default)]
+    usage: TokenUsage,
 }
 
 impl TranslationResult {
@@ -144,6 +384,12 @@ impl TranslationResult {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct NameCheckpoint {
+    types: Vec<(String, TypeSort, String)>,
+    terms: BTreeMap<String, String>,
+}
+
 #[derive(Debug)]
 struct DependencyPrefixes {
     translation_prefix: Vec<String>,
@@ -155,15 +401,17 @@ struct FixContext<'a> {
     prefix: &'a str,
     code: String,
     names: &'a BTreeSet<String>,
+    max_fixes: usize,
     result: Option<TypeCheckingResult>,
 }
 
 impl<'a> FixContext<'a> {
-    fn new(prefix: &'a str, code: String, names: &'a BTreeSet<String>) -> Self {
+    fn new(prefix: &'a str, code: String, names: &'a BTreeSet<String>, max_fixes: usize) -> Self {
         let mut this = Self {
             prefix,
             code,
             names,
+            max_fixes,
             result: None,
         };
         this.check();
@@ -264,6 +512,51 @@ pub enum SigDiffReason {
 
 static PREAMBLE: &str = "extern crate once_cell;extern crate libc;";
 
+/// Tells the model how to translate the variable arguments of a function.
+static VARIADIC_NOTE: &str = "`...` is translated to a last parameter `args: &[VaArg]`, \
+     and each `va_arg` takes the next element of `args`.";
+
+/// The variable arguments of a variadic function after the default argument promotions.
+static VA_ARG: &str = "#[derive(Clone, Copy, Debug)]
+pub enum VaArg {
+    Int(i64),
+    Double(f64),
+    Ptr(*mut libc::c_void),
+}";
+
+static CHECKPOINT_SUBDIRS: [&str; 4] = ["types", "variables", "functions", "macros"];
+
+static ARGV_PROLOGUE: &str = "    let mut args: Vec<Vec<u8>> = std::env::args_os()
+        .map(|arg| {
+            let mut arg = std::os::unix::ffi::OsStringExt::into_vec(arg);
+            arg.push(0);
+            arg
+        })
+        .collect();
+    let mut argv: Vec<*mut libc::c_char> = args
+        .iter_mut()
+        .map(|arg| arg.as_mut_ptr() as *mut libc::c_char)
+        .chain(std::iter::once(std::ptr::null_mut()))
+        .collect();
+    let argc = args.len() as i32;
+";
+
+static ENVP_PROLOGUE: &str = "    let mut vars: Vec<Vec<u8>> = std::env::vars_os()
+        .map(|(key, value)| {
+            let mut var = std::os::unix::ffi::OsStringExt::into_vec(key);
+            var.push(b'=');
+            var.extend(std::os::unix::ffi::OsStringExt::into_vec(value));
+            var.push(0);
+            var
+        })
+        .collect();
+    let mut envp: Vec<*mut libc::c_char> = vars
+        .iter_mut()
+        .map(|var| var.as_mut_ptr() as *mut libc::c_char)
+        .chain(std::iter::once(std::ptr::null_mut()))
+        .collect();
+";
+
 impl<'ast> Translator<'ast> {
     pub fn new(
         program: &'ast Program,
@@ -275,6 +568,15 @@ impl<'ast> Translator<'ast> {
         let enums = program.enums();
         let (variables, protos) = program.variables();
         let functions = program.functions();
+        let macros = if config.recover_macros {
+            program
+                .macros
+                .iter()
+                .map(|(n, m)| (n.as_str(), m))
+                .collect()
+        } else {
+            BTreeMap::new()
+        };
 
         let mut cg: BTreeMap<_, BTreeSet<_>> = BTreeMap::new();
         for (name, t) in &typedefs {
@@ -317,11 +619,7 @@ impl<'ast> Translator<'ast> {
             .map(|(name, function)| {
                 (
                     *name,
-                    function
-                        .dependencies
-                        .iter()
-                        .map(|v| v.node.name.as_str())
-                        .collect(),
+                    function.dependencies.iter().map(|v| v.name).collect(),
                 )
             })
             .collect();
@@ -334,7 +632,7 @@ impl<'ast> Translator<'ast> {
                     variable
                         .dependencies
                         .iter()
-                        .map(|callee| callee.node.name.as_str())
+                        .map(|callee| callee.name)
                         .collect(),
                 )
             })
@@ -342,26 +640,79 @@ impl<'ast> Translator<'ast> {
         let (variable_graph, variable_elem_map) = graph::compute_sccs(&cg);
         let transitive_variables = graph::transitive_closure(cg);
 
-        let cg = functions
+        let mut function_pointer_contexts: BTreeMap<_, BTreeSet<_>> = BTreeMap::new();
+        let uses = functions
+            .values()
+            .flat_map(|f| &f.function_pointers)
+            .chain(variables.values().flat_map(|v| &v.function_pointers));
+        for u in uses {
+            function_pointer_contexts
+                .entry(u.function.name)
+                .or_default()
+                .insert(u.context.clone());
+        }
+
+        let cg: BTreeMap<_, BTreeSet<_>> = functions
             .iter()
             .map(|(name, function)| {
-                (
-                    *name,
-                    function
-                        .callees
-                        .iter()
-                        .map(|callee| callee.node.name.as_str())
-                        .collect(),
-                )
+                let mut callees: BTreeSet<_> = function
+                    .callees
+                    .iter()
+                    .chain(function.function_pointers.iter().map(|u| &u.function))
+                    .map(|callee| callee.name)
+                    .collect();
+                let vars = function.dependencies.iter().flat_map(|v| {
+                    let trans = transitive_variables.get(v.name).into_iter().flatten();
+                    std::iter::once(&v.name).chain(trans)
+                });
+                for v in vars.flat_map(|v| variables.get(v)) {
+                    callees.extend(v.function_pointers.iter().map(|u| u.function.name));
+                }
+                (*name, callees)
             })
             .chain(protos.keys().map(|name| (*name, BTreeSet::new())))
             .collect();
-        let (function_graph, function_elem_map) = graph::compute_sccs(&cg);
+        let (mut function_graph, function_elem_map) = graph::compute_sccs(&cg);
+
+        // A call through a function pointer may call any function used as a function pointer
+        // that takes the same number of arguments. Such calls are too imprecise to merge
+        // functions into one component, so they only order the components they do not close a
+        // cycle between.
+        let function_ids: BTreeMap<_, _> = function_elem_map
+            .iter()
+            .flat_map(|(id, set)| set.iter().map(move |name| (*name, *id)))
+            .collect();
+        let indirect_edges = functions.iter().flat_map(|(name, function)| {
+            let targets = function.indirect_calls.iter().flat_map(|n| {
+                function_pointer_contexts.keys().filter(|f| {
+                    functions.get(*f).map_or(false, |f| {
+                        let sig = &f.type_signature;
+                        sig.params.len() == *n || (sig.variadic && sig.params.len() < *n)
+                    })
+                })
+            });
+            targets.map(|f| (function_ids[name], function_ids[f]))
+        });
+        graph::add_acyclic_edges(&mut function_graph, indirect_edges);
         let called_functions = cg.values().flatten().copied().collect();
+        let function_edges = cg.clone();
         let transitive_functions = graph::transitive_closure(cg);
 
         let inner = TranslatorInner::default();
 
+        let variadic_protos = protos
+            .values()
+            .any(|p| c_parser::is_variadic(&p.declarator.node.declarator.node));
+        let preamble = if variadic_protos
+            || functions
+                .values()
+                .any(|f| f.type_signature.variadic || !f.variadic_indirect_calls.is_empty())
+        {
+            format!("{}\n{}", PREAMBLE, VA_ARG)
+        } else {
+            PREAMBLE.to_string()
+        };
+
         Self {
             program,
             typedefs,
@@ -370,6 +721,7 @@ impl<'ast> Translator<'ast> {
             variables,
             protos,
             functions,
+            macros,
             custom_types,
             transitive_types,
             transitive_variables,
@@ -382,22 +734,245 @@ impl<'ast> Translator<'ast> {
             variable_elem_map,
             function_graph,
             function_elem_map,
+            function_edges,
             called_functions,
+            function_pointer_contexts,
             client,
             new_type_names: BTreeMap::new(),
             new_term_names: BTreeMap::new(),
+            new_enumerator_names: BTreeMap::new(),
             inner: RwLock::new(inner),
+            preamble,
             config,
+            checkpoint_dir: None,
+            selected: None,
+        }
+    }
+
+    pub fn select(&mut self, selection: &Selection) {
+        if selection.is_empty() {
+            return;
+        }
+
+        let mut selected = SelectedItems::default();
+        for (name, f) in &self.functions {
+            if selection.functions.iter().any(|p| glob_match(p, name))
+                || selection.matches_file(f.path)
+            {
+                selected.functions.insert(*name);
+            }
+        }
+        for (name, v) in &self.variables {
+            if selection.matches_file(v.path) {
+                selected.variables.insert(*name);
+            }
+        }
+        for ty in &self.custom_types {
+            if selection.types.iter().any(|p| glob_match(p, ty.name))
+                || selection.matches_file(self.type_path(ty))
+            {
+                selected.types.insert(*ty);
+            }
+        }
+
+        for name in &selected.functions {
+            let func = self.functions.get(name).unwrap();
+            selected.macros.extend(func.macros.iter().map(|m| m.name));
+            for callee in self.function_references(func) {
+                let callee = callee.name;
+                if let Some((callee, _)) = self.protos.get_key_value(callee) {
+                    selected.protos.insert(*callee);
+                } else if let Some((callee, _)) = self.functions.get_key_value(callee) {
+                    if !selected.functions.contains(callee) {
+                        selected.stubs.insert(*callee);
+                    }
+                }
+            }
+            selected
+                .variables
+                .extend(self.function_variables.get(name).unwrap());
+        }
+        let mut macros: Vec<_> = selected.macros.iter().copied().collect();
+        while let Some(name) = macros.pop() {
+            let m = some_or!(self.macros.get(name), continue);
+            let (types, vars, funcs) = self.macro_dependencies(m);
+            selected.types.extend(types);
+            selected.protos.extend(funcs);
+            for var in vars {
+                if self.macros.contains_key(var) {
+                    if selected.macros.insert(var) {
+                        macros.push(var);
+                    }
+                } else {
+                    selected.variables.insert(var);
+                }
+            }
+        }
+        let variables: Vec<_> = selected
+            .variables
+            .iter()
+            .flat_map(|v| self.transitive_variables.get(v))
+            .flatten()
+            .copied()
+            .collect();
+        selected.variables.extend(variables);
+
+        let terms = selected
+            .variables
+            .iter()
+            .chain(&selected.protos)
+            .chain(&selected.functions)
+            .chain(&selected.stubs);
+        let types: Vec<_> = terms
+            .flat_map(|name| self.term_types.get(name))
+            .flatten()
+            .copied()
+            .collect();
+        selected.types.extend(types);
+        let types: Vec<_> = selected
+            .types
+            .iter()
+            .flat_map(|ty| self.transitive_types.get(ty))
+            .flatten()
+            .copied()
+            .collect();
+        selected.types.extend(types);
+
+        self.selected = Some(selected);
+    }
+
+    pub fn enable_checkpoint<P: AsRef<Path>>(&mut self, dir: P, resume: bool) {
+        let dir = dir.as_ref().to_path_buf();
+        for sub in CHECKPOINT_SUBDIRS {
+            let sub = dir.join(sub);
+            if !resume && sub.exists() {
+                fs::remove_dir_all(&sub).unwrap();
+            }
+            fs::create_dir_all(sub).unwrap();
+        }
+        let names = dir.join("names.json");
+        if !resume && names.exists() {
+            fs::remove_file(names).unwrap();
+        }
+        self.checkpoint_dir = Some(dir);
+        if resume {
+            self.load_checkpoint();
+        }
+    }
+
+    fn load_checkpoint(&mut self) {
+        let dir = self.checkpoint_dir.clone().unwrap();
+
+        if let Ok(names) = fs::read_to_string(dir.join("names.json")) {
+            let names: NameCheckpoint = serde_json::from_str(&names).unwrap();
+            for (name, sort, new_name) in names.types {
+                let ty = some_or!(
+                    self.custom_types
+                        .iter()
+                        .find(|ty| ty.name == name && ty.sort == sort),
+                    continue
+                );
+                self.new_type_names.insert(*ty, new_name);
+            }
+            for (name, new_name) in names.terms {
+                let name = some_or!(self.term_name(&name), continue);
+                self.new_term_names.insert(name, new_name);
+            }
+            self.name_enumerators();
+        }
+
+        let mut inner = self.inner.write().unwrap();
+        for (_, (name, sort, translated)) in
+            read_checkpoint_dir::<(String, TypeSort, _)>(&dir.join("types"))
+        {
+            let ty = some_or!(
+                self.custom_types
+                    .iter()
+                    .find(|ty| ty.name == name && ty.sort == sort),
+                continue
+            );
+            inner.add_names(&translated);
+            inner.translated_types.insert(*ty, translated);
+        }
+        for (name, translated) in read_checkpoint_dir(&dir.join("variables")) {
+            let name = some_or!(self.term_name(&name), continue);
+            inner.add_names(&translated);
+            inner.translated_variables.insert(name, translated);
+        }
+        for (name, translated) in read_checkpoint_dir(&dir.join("functions")) {
+            let name = some_or!(self.term_name(&name), continue);
+            inner.add_names(&translated);
+            inner.translated_functions.insert(name, translated);
+        }
+        for (name, translated) in read_checkpoint_dir(&dir.join("macros")) {
+            let name = some_or!(self.term_name(&name), continue);
+            inner.add_names(&translated);
+            inner.translated_macros.insert(name, translated);
+        }
+    }
+
+    fn term_name(&self, name: &str) -> Option<&'ast str> {
+        if let Some((name, _)) = self.variables.get_key_value(name) {
+            Some(*name)
+        } else if let Some((name, _)) = self.protos.get_key_value(name) {
+            Some(*name)
+        } else if let Some((name, _)) = self.functions.get_key_value(name) {
+            Some(*name)
+        } else {
+            self.macros.get_key_value(name).map(|(name, _)| *name)
+        }
+    }
+
+    fn save_names(&self) {
+        let dir = some_or!(&self.checkpoint_dir, return);
+        let names = NameCheckpoint {
+            types: self
+                .new_type_names
+                .iter()
+                .map(|(ty, new_name)| (ty.name.to_string(), ty.sort, new_name.clone()))
+                .collect(),
+            terms: self
+                .new_term_names
+                .iter()
+                .map(|(name, new_name)| (name.to_string(), new_name.clone()))
+                .collect(),
+        };
+        write_checkpoint(&dir.join("names.json"), &names);
+    }
+
+    fn save_type(&self, ty: &CustomType<'_>, translated: &TranslationResult) {
+        let dir = some_or!(&self.checkpoint_dir, return);
+        let path = dir
+            .join("types")
+            .join(format!("{:?}.{}.json", ty.sort, ty.name));
+        write_checkpoint(&path, &(ty.name, ty.sort, translated));
+    }
+
+    fn save_term(&self, sub: &str, name: &str, translated: &TranslationResult) {
+        let dir = some_or!(&self.checkpoint_dir, return);
+        write_checkpoint(&dir.join(sub).join(format!("{}.json", name)), translated);
+    }
+
+    fn program_size(&self) -> ProgramSize {
+        ProgramSize {
+            lines: self.lines_of_code(),
+            types: self.typedefs.len() + self.structs.len() + self.enums.len(),
+            variables: self.variables.len(),
+            protos: self.protos.len(),
+            functions: self.functions.len(),
+            calls: self.functions.values().map(|f| f.calls).sum(),
         }
     }
 
     pub fn show_information(&self) {
-        let lines = self.lines_of_code();
-        let types = self.typedefs.len() + self.structs.len() + self.enums.len();
-        let variables = self.variables.len();
-        let protos = self.protos.len();
-        let functions = self.functions.len();
-        let calls: usize = self.functions.values().map(|f| f.calls).sum();
+        let ProgramSize {
+            lines,
+            types,
+            variables,
+            protos,
+            functions,
+            calls,
+        } = self.program_size();
         println!(
             "{}\n{}\n{}\n{}\n{}\n{}",
             lines, types, variables, protos, functions, calls
@@ -413,6 +988,10 @@ impl<'ast> Translator<'ast> {
         );
     }
 
+    pub fn cache_misses(&self) -> Vec<CacheMiss> {
+        self.client.cache_misses()
+    }
+
     fn lines_of_code(&self) -> usize {
         let spans: Vec<_> = self
             .typedefs
@@ -455,67 +1034,40 @@ impl<'ast> Translator<'ast> {
 
     pub fn show_error_num(&self) {
         let inner = self.inner.read().unwrap();
-        fn aux(
-            translated: &BTreeMap<&str, TranslationResult>,
-            transitive: &BTreeMap<&str, BTreeSet<&str>>,
-        ) {
-            let mut errors = 0;
-            let mut protos = 0;
-            let mut longs = 0;
-            let mut fails = 0;
-            let mut w_errors = 0;
-            let mut wo_errors = 0;
-            let mut wo_error_names = vec![];
-            let mut no_trans_errors = 0;
-            let mut no_trans_error_names = vec![];
-            for (name, t) in translated {
-                if t.proto {
-                    protos += 1;
-                } else if t.too_long {
-                    longs += 1;
-                } else if t.failed {
-                    fails += 1;
-                } else if t.errors > 0 {
-                    w_errors += 1;
-                    errors += t.errors;
-                } else {
-                    wo_errors += 1;
-                    wo_error_names.push(*name);
-                    let callees = &transitive[name];
-                    if callees.iter().all(|c| {
-                        let t = some_or!(translated.get(c), return true);
-                        t.no_error()
-                    }) {
-                        no_trans_errors += 1;
-                        no_trans_error_names.push(*name);
-                    }
-                }
-            }
-            let wo_error_names: String = wo_error_names.iter().copied().intersperse(" ").collect();
-            let no_trans_error_names: String = no_trans_error_names
+        let stats = [
+            error_stats(&inner.translated_variables, &self.transitive_variables),
+            error_stats(&inner.translated_functions, &self.transitive_functions),
+        ];
+        for stats in stats {
+            let wo_error_names: String = stats
+                .wo_error_names
                 .iter()
-                .copied()
+                .map(|s| s.as_str())
+                .intersperse(" ")
+                .collect();
+            let no_trans_error_names: String = stats
+                .no_trans_error_names
+                .iter()
+                .map(|s| s.as_str())
                 .intersperse(" ")
                 .collect();
             println!(
                 "{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
-                errors,
-                protos,
-                longs,
-                fails,
-                w_errors,
-                wo_errors,
-                no_trans_errors,
+                stats.errors,
+                stats.protos,
+                stats.longs,
+                stats.fails,
+                stats.w_errors,
+                stats.wo_errors,
+                stats.no_trans_errors,
                 wo_error_names,
                 no_trans_error_names
             );
         }
-        aux(&inner.translated_variables, &self.transitive_variables);
-        aux(&inner.translated_functions, &self.transitive_functions);
     }
 
-    pub fn per_stage(&self) -> String {
-        let mut per_stage: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    fn stage_stats(&self) -> BTreeMap<usize, StageStats> {
+        let mut per_stage: BTreeMap<_, StageStats> = BTreeMap::new();
         let inner = self.inner.read().unwrap();
         for res in inner
             .translated_types
@@ -523,70 +1075,277 @@ impl<'ast> Translator<'ast> {
             .chain(inner.translated_variables.values())
             .chain(inner.translated_functions.values())
         {
-            per_stage.entry(res.stage).or_default().push(res.errors);
+            let stats = per_stage.entry(res.stage).or_default();
+            stats.definitions += 1;
+            stats.errors += res.errors;
         }
         per_stage
+    }
+
+    pub fn per_stage(&self) -> String {
+        self.stage_stats()
             .into_iter()
-            .map(|(stage, errors)| {
+            .map(|(stage, stats)| {
                 format!(
                     "{}: {} definitions, {} errors",
-                    stage,
-                    errors.len(),
-                    errors.iter().sum::<usize>()
+                    stage, stats.definitions, stats.errors
                 )
             })
             .collect::<Vec<_>>()
             .join("\n")
     }
 
-    pub fn show_type(&self) {
+    fn type_stats(&self) -> TypeStats {
         let inner = self.inner.read().unwrap();
-        let mut fulls = 0;
-        let mut partials = 0;
-        let mut uns = 0;
-        let mut rtys: BTreeMap<_, usize> = BTreeMap::new();
-        let mut ctys: BTreeMap<_, usize> = BTreeMap::new();
+        let mut stats = TypeStats::default();
         for f in inner.translated_functions.values() {
             let tys = &f.types;
             if f.proto || f.too_long || f.failed {
                 continue;
             }
             if tys.iter().all(|ty| !compiler::is_c_type(ty)) {
-                fulls += 1;
+                stats.fulls += 1;
             } else if tys.iter().all(|ty| compiler::is_c_type(ty)) {
-                uns += 1;
+                stats.uns += 1;
             } else {
-                partials += 1;
+                stats.partials += 1;
             }
             for ty in tys {
                 if compiler::is_c_type(ty) {
-                    *ctys.entry(ty).or_default() += 1;
+                    *stats.c_types.entry(ty.clone()).or_default() += 1;
                 } else {
-                    *rtys.entry(ty).or_default() += 1;
+                    *stats.rust_types.entry(ty.clone()).or_default() += 1;
                 }
             }
         }
-        let rty_n = rtys.values().sum::<usize>();
-        let rty_kind_n = rtys.len();
-        let rtys_str = rtys
-            .into_iter()
+        stats
+    }
+
+    pub fn show_headers(&self) {
+        for header in &self.program.library_headers {
+            println!("library {}", header);
+        }
+        for header in &self.program.project_headers {
+            println!("project {}", header);
+        }
+    }
+
+    pub fn show_type(&self) {
+        let stats = self.type_stats();
+        let rty_n = stats.rust_types.values().sum::<usize>();
+        let rty_kind_n = stats.rust_types.len();
+        let rtys_str = stats
+            .rust_types
+            .iter()
             .map(|(ty, n)| format!("{} {}", ty, n))
             .collect::<Vec<_>>()
             .join(", ");
-        let cty_n = ctys.values().sum::<usize>();
-        let cty_kind_n = ctys.len();
-        let ctys_str = ctys
-            .into_iter()
+        let cty_n = stats.c_types.values().sum::<usize>();
+        let cty_kind_n = stats.c_types.len();
+        let ctys_str = stats
+            .c_types
+            .iter()
             .map(|(ty, n)| format!("{} {}", ty, n))
             .collect::<Vec<_>>()
             .join(", ");
 
         println!(
             "{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
-            fulls, partials, uns, rty_n, rty_kind_n, rtys_str, cty_n, cty_kind_n, ctys_str
+            stats.fulls,
+            stats.partials,
+            stats.uns,
+            rty_n,
+            rty_kind_n,
+            rtys_str,
+            cty_n,
+            cty_kind_n,
+            ctys_str
         );
     }
 
+    pub fn report(&self) -> Report {
+        let inner = self.inner.read().unwrap();
+        let mut items = vec![];
+        for (ty, t) in &inner.translated_types {
+            let rust_name = self.new_type_names.get(ty).cloned().unwrap_or_default();
+            let kind = match ty.sort {
+                TypeSort::Typedef => "typedef",
+                TypeSort::Struct => "struct",
+                TypeSort::Union => "union",
+                TypeSort::Enum => "enum",
+            };
+            items.push(ItemReport::new(
+                ty.name,
+                rust_name,
+                kind,
+                self.type_path(ty),
+                t,
+            ));
+        }
+        for (name, t) in &inner.translated_variables {
+            let rust_name = self.new_term_names.get(name).cloned().unwrap_or_default();
+            let path = self.term_path(name);
+            items.push(ItemReport::new(name, rust_name, "variable", path, t));
+        }
+        for (name, t) in &inner.translated_functions {
+            let rust_name = self.new_term_names.get(name).cloned().unwrap_or_default();
+            let kind = if self.protos.contains_key(name) {
+                "proto"
+            } else {
+                "function"
+            };
+            let path = self.term_path(name);
+            items.push(ItemReport::new(name, rust_name, kind, path, t));
+        }
+        for (name, t) in &inner.translated_macros {
+            let rust_name = self.new_term_names.get(name).cloned().unwrap_or_default();
+            let path = self.term_path(name);
+            items.push(ItemReport::new(name, rust_name, "macro", path, t));
+        }
+        let errors = ErrorReport {
+            variables: error_stats(&inner.translated_variables, &self.transitive_variables),
+            functions: error_stats(&inner.translated_functions, &self.transitive_functions),
+        };
+        drop(inner);
+
+        Report {
+            items,
+            program_size: self.program_size(),
+            errors,
+            usage: UsageReport {
+                request_tokens: self.client.request_tokens(),
+                response_tokens: self.client.response_tokens(),
+                response_time: self.client.response_time(),
+            },
+            types: self.type_stats(),
+            stages: self.stage_stats(),
+            cache_misses: self.cache_misses(),
+            headers: HeaderReport {
+                library: self.program.library_headers.iter().cloned().collect(),
+                project: self.program.project_headers.iter().cloned().collect(),
+            },
+            skipped_files: self.program.diagnostics.clone(),
+        }
+    }
+
+    pub fn provenance(&self) -> Vec<Provenance> {
+        let inner = self.inner.read().unwrap();
+        let provenance = |c_name: &str,
+                          rust_name: Option<&String>,
+                          kind,
+                          path: &str,
+                          location: Option<&Location>,
+                          t: &TranslationResult| Provenance {
+            c_name: c_name.to_string(),
+            rust_name: rust_name.cloned().unwrap_or_default(),
+            kind,
+            path: path.to_string(),
+            location: location.cloned(),
+            rust_items: t.items.iter().map(|i| i.name.clone()).collect(),
+        };
+        let mut items = vec![];
+        for (ty, t) in &inner.translated_types {
+            let kind = match ty.sort {
+                TypeSort::Typedef => "typedef",
+                TypeSort::Struct => "struct",
+                TypeSort::Union => "union",
+                TypeSort::Enum => "enum",
+            };
+            items.push(provenance(
+                ty.name,
+                self.new_type_names.get(ty),
+                kind,
+                self.type_path(ty),
+                self.program.type_location(ty),
+                t,
+            ));
+        }
+        let terms = inner
+            .translated_variables
+            .iter()
+            .map(|(name, t)| (name, "variable", t))
+            .chain(inner.translated_functions.iter().map(|(name, t)| {
+                let kind = if self.protos.contains_key(name) {
+                    "proto"
+                } else {
+                    "function"
+                };
+                (name, kind, t)
+            }))
+            .chain(
+                inner
+                    .translated_macros
+                    .iter()
+                    .map(|(name, t)| (name, "macro", t)),
+            );
+        for (name, kind, t) in terms {
+            items.push(provenance(
+                name,
+                self.new_term_names.get(name),
+                kind,
+                self.term_path(name),
+                self.program.term_location(name),
+                t,
+            ));
+        }
+        items
+    }
+
+    /// Runs every phase of the translation with the client, which must be in
+    /// dry-run mode so that it only counts the requests it would send, and
+    /// reports the requests of each phase. Items restored from a checkpoint
+    /// are not translated again, and nothing is written to the checkpoint.
+    pub async fn estimate(&mut self) -> Estimate {
+        self.checkpoint_dir = None;
+        self.config.quiet = true;
+        let restored: BTreeSet<_> = {
+            let inner = self.inner.read().unwrap();
+            inner
+                .translated_variables
+                .keys()
+                .chain(inner.translated_functions.keys())
+                .copied()
+                .collect()
+        };
+
+        let mut estimate = Estimate::default();
+        let usage = self.client_usage();
+        self.translate_names().await;
+        estimate.names = self.client_usage().since(usage);
+        let usage = self.client_usage();
+        self.translate_types().await;
+        estimate.types = self.client_usage().since(usage);
+        let usage = self.client_usage();
+        self.translate_variables().await;
+        estimate.variables = self.client_usage().since(usage);
+        let usage = self.client_usage();
+        self.translate_protos().await;
+        estimate.protos = self.client_usage().since(usage);
+        let usage = self.client_usage();
+        self.translate_macros().await;
+        estimate.macros = self.client_usage().since(usage);
+        let usage = self.client_usage();
+        self.translate_functions().await;
+        estimate.functions = self.client_usage().since(usage);
+
+        let inner = self.inner.read().unwrap();
+        estimate.too_long = inner
+            .translated_variables
+            .iter()
+            .chain(inner.translated_functions.iter())
+            .filter(|(name, t)| t.too_long && !restored.contains(*name))
+            .count();
+        estimate
+    }
+
+    fn client_usage(&self) -> PhaseEstimate {
+        PhaseEstimate {
+            requests: self.client.requests(),
+            request_tokens: self.client.request_tokens(),
+            response_tokens: self.client.response_tokens(),
+        }
+    }
+
     fn existing_names(&self) -> BTreeSet<String> {
         let inner = self.inner.read().unwrap();
         inner
@@ -614,35 +1373,260 @@ impl<'ast> Translator<'ast> {
         items.into_values().collect()
     }
 
+    /// Maps each translated item to the comment preceding its C declaration.
+    fn item_comments(&self, inner: &TranslatorInner<'ast>) -> BTreeMap<(bool, &str), &str> {
+        let mut comments = BTreeMap::new();
+        for ty in inner.translated_types.keys() {
+            let new_name = some_or!(self.new_type_names.get(ty), continue);
+            let comment = some_or!(self.program.type_comment(ty), continue);
+            comments.entry((true, new_name.as_str())).or_insert(comment);
+        }
+        let terms = inner
+            .translated_variables
+            .keys()
+            .chain(inner.translated_functions.keys())
+            .chain(inner.translated_macros.keys());
+        for name in terms {
+            let new_name = some_or!(self.new_term_names.get(name), continue);
+            let comment = some_or!(self.program.term_comment(name), continue);
+            comments
+                .entry((false, new_name.as_str()))
+                .or_insert(comment);
+        }
+        comments
+    }
+
+    /// Maps each translated item to the position of its C declaration if
+    /// `provenance_comments` is set.
+    fn item_origins(&self, inner: &TranslatorInner<'ast>) -> BTreeMap<(bool, &str), String> {
+        let mut origins = BTreeMap::new();
+        if !self.config.provenance_comments {
+            return origins;
+        }
+        let origin = |l: &Location| format!("{}:{}", l.file.display(), l.line);
+        for ty in inner.translated_types.keys() {
+            let new_name = some_or!(self.new_type_names.get(ty), continue);
+            let location = some_or!(self.program.type_location(ty), continue);
+            origins
+                .entry((true, new_name.as_str()))
+                .or_insert_with(|| origin(location));
+        }
+        let terms = inner
+            .translated_variables
+            .keys()
+            .chain(inner.translated_functions.keys())
+            .chain(inner.translated_macros.keys());
+        for name in terms {
+            let new_name = some_or!(self.new_term_names.get(name), continue);
+            let location = some_or!(self.program.term_location(name), continue);
+            origins
+                .entry((false, new_name.as_str()))
+                .or_insert_with(|| origin(location));
+        }
+        origins
+    }
+
+    fn item_code(
+        comments: &BTreeMap<(bool, &str), &str>,
+        origins: &BTreeMap<(bool, &str), String>,
+        item: &ParsedItem,
+    ) -> String {
+        let is_type = matches!(item.sort, ItemSort::Type(_));
+        let key = (is_type, item.name.as_str());
+        let code = match comments.get(&key) {
+            Some(comment) => format!("{}\n{}", comment_lines(comment, "///"), item.get_code()),
+            None => item.get_code(),
+        };
+        match origins.get(&key) {
+            Some(origin) => format!("// from {}\n{}", origin, code),
+            None => code,
+        }
+    }
+
     pub fn code(&self) -> String {
         let inner = self.inner.read().unwrap();
+        let comments = self.item_comments(&inner);
+        let origins = self.item_origins(&inner);
         let items = Self::dedup_items(
             inner
                 .translated_types
                 .values()
+                .chain(inner.translated_macros.values())
                 .chain(inner.translated_variables.values())
                 .chain(inner.translated_functions.values())
                 .flat_map(|t| &t.items)
                 .collect(),
         );
-        std::iter::once(PREAMBLE.to_string())
-            .chain(items.into_iter().map(|i| i.get_code()))
-            .chain(std::iter::once("fn main() {}".to_string()))
+        std::iter::once(self.preamble.clone())
+            .chain(
+                items
+                    .into_iter()
+                    .map(|i| Self::item_code(&comments, &origins, i)),
+            )
+            .chain(std::iter::once(self.entry_point(&inner)))
             .intersperse("\n".to_string())
             .collect()
     }
 
+    fn main_signature(&self, name: &str) -> Option<String> {
+        if name != "main" {
+            return None;
+        }
+        let func = self.functions.get(name)?;
+        let new_name = self.new_term_name(name);
+        let params = match func.type_signature.params.len() {
+            0 => "",
+            2 => "argc: i32, argv: *mut *mut libc::c_char",
+            3 => "argc: i32, argv: *mut *mut libc::c_char, envp: *mut *mut libc::c_char",
+            _ => return None,
+        };
+        Some(format!("fn {}({}) -> i32", new_name, params))
+    }
+
+    fn entry_point(&self, inner: &TranslatorInner<'_>) -> String {
+        if !inner.translated_functions.contains_key("main") {
+            return "fn main() {}".to_string();
+        }
+        let func = some_or!(
+            self.functions.get("main"),
+            return "fn main() {}".to_string()
+        );
+        let new_name = self.new_term_names.get("main").unwrap();
+        let (prologue, args) = match func.type_signature.params.len() {
+            0 => (String::new(), ""),
+            2 => (ARGV_PROLOGUE.to_string(), "argc, argv.as_mut_ptr()"),
+            3 => (
+                format!("{}{}", ARGV_PROLOGUE, ENVP_PROLOGUE),
+                "argc, argv.as_mut_ptr(), envp.as_mut_ptr()",
+            ),
+            _ => return "fn main() {}".to_string(),
+        };
+        format!(
+            "fn main() {{\n{}    std::process::exit({}({}));\n}}",
+            prologue, new_name, args
+        )
+    }
+
+    fn type_path(&self, ty: &CustomType<'_>) -> &'ast str {
+        match ty.sort {
+            TypeSort::Typedef => self.typedefs.get(ty.name).unwrap().path,
+            TypeSort::Struct | TypeSort::Union => self.structs.get(ty.name).unwrap().path,
+            TypeSort::Enum => self.enums.get(ty.name).unwrap().path,
+        }
+    }
+
+    fn term_path(&self, name: &str) -> &'ast str {
+        if let Some(v) = self.variables.get(name) {
+            v.path
+        } else if let Some(f) = self.functions.get(name) {
+            f.path
+        } else if let Some(p) = self.protos.get(name) {
+            p.path
+        } else {
+            let m: &'ast Macro = self.macros[name];
+            &m.path
+        }
+    }
+
+    pub fn write_project<P: AsRef<Path>>(&self, dir: P) {
+        let dir = dir.as_ref();
+        let inner = self.inner.read().unwrap();
+        let comments = self.item_comments(&inner);
+        let origins = self.item_origins(&inner);
+
+        let mut seen = BTreeSet::new();
+        let mut modules: BTreeMap<&str, Vec<&ParsedItem>> = BTreeMap::new();
+        let results = inner
+            .translated_types
+            .iter()
+            .map(|(ty, t)| (self.type_path(ty), t))
+            .chain(
+                inner
+                    .translated_macros
+                    .iter()
+                    .chain(&inner.translated_variables)
+                    .chain(&inner.translated_functions)
+                    .map(|(name, t)| (self.term_path(name), t)),
+            );
+        for (path, t) in results {
+            for item in &t.items {
+                let n = match &item.sort {
+                    ItemSort::Type(_) => 0,
+                    ItemSort::Variable(_) => 1,
+                    ItemSort::Function(_) => 2,
+                    _ => panic!(),
+                };
+                if seen.insert((n, item.name.as_str())) {
+                    modules.entry(path).or_default().push(item);
+                }
+            }
+        }
+
+        let mut module_names = BTreeSet::new();
+        let mut root = vec!["#![allow(unused)]".to_string(), self.preamble.clone()];
+        let src = dir.join("src");
+        fs::create_dir_all(&src).unwrap();
+        for (path, items) in modules {
+            let name = module_name(path, &module_names);
+            let header = self
+                .program
+                .file_comment(path)
+                .map(|c| comment_lines(c, "//"));
+            let code: String = header
+                .into_iter()
+                .chain(std::iter::once("use crate::*;".to_string()))
+                .chain(
+                    Self::dedup_items(items)
+                        .into_iter()
+                        .map(|i| Self::item_code(&comments, &origins, i)),
+                )
+                .intersperse("\n".to_string())
+                .collect();
+            let code = compiler::make_public(&code).unwrap_or(code);
+            fs::write(src.join(format!("{}.rs", name)), code + "\n").unwrap();
+            root.push(format!("mod {0};\npub use {0}::*;", name));
+            module_names.insert(name);
+        }
+
+        let is_bin =
+            self.functions.contains_key("main") && inner.translated_functions.contains_key("main");
+        if is_bin {
+            root.push(self.entry_point(&inner));
+        }
+        let root_file = if is_bin { "main.rs" } else { "lib.rs" };
+        fs::write(src.join(root_file), root.join("\n") + "\n").unwrap();
+
+        let crate_name = dir
+            .canonicalize()
+            .ok()
+            .and_then(|d| d.file_name().map(|s| sanitize_name(&s.to_string_lossy())))
+            .unwrap_or_else(|| "translated".to_string());
+        let manifest = format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n{}",
+            crate_name, *DEPENDENCIES
+        );
+        fs::write(dir.join("Cargo.toml"), manifest).unwrap();
+    }
+
     fn make_replace_vec<'a>(
         &'a self,
-        types: Option<&[TypeDependency<'a>]>,
-        vars: Option<&[&Node<Identifier>]>,
-        callees: Option<&[&Node<Identifier>]>,
+        inner: &TranslatorInner<'ast>,
+        types: Option<&[TypeDependency<'ast>]>,
+        vars: Option<&[TermDependency<'_>]>,
+        callees: Option<&[TermDependency<'_>]>,
     ) -> Vec<(Span, &'a str)> {
         let mut vec = vec![];
 
         if let Some(deps) = types {
             for d in deps {
-                if let Some(x) = self.new_type_names.get(&d.typ) {
+                let new_name = match d.enumerator {
+                    Some(e) => self
+                        .new_enumerator_names
+                        .get(e)
+                        .filter(|path| Self::is_translated_variant(inner, &d.typ, path)),
+                    None => self.new_type_names.get(&d.typ),
+                };
+                if let Some(x) = new_name {
                     vec.push((d.span, x.as_str()));
                 }
             }
@@ -650,7 +1634,7 @@ impl<'ast> Translator<'ast> {
 
         if let Some(deps) = vars {
             for d in deps {
-                if let Some(x) = self.new_term_names.get(d.node.name.as_str()) {
+                if let Some(x) = self.new_term_names.get(d.name) {
                     vec.push((d.span, x.as_str()));
                 }
             }
@@ -658,7 +1642,7 @@ impl<'ast> Translator<'ast> {
 
         if let Some(deps) = callees {
             for d in deps {
-                if let Some(x) = self.new_term_names.get(d.node.name.as_str()) {
+                if let Some(x) = self.new_term_names.get(d.name) {
                     vec.push((d.span, x.as_str()));
                 }
             }
@@ -667,6 +1651,24 @@ impl<'ast> Translator<'ast> {
         vec
     }
 
+    /// Checks whether `path` names a variant of the Rust enum `ty` was
+    /// translated to.
+    fn is_translated_variant(
+        inner: &TranslatorInner<'ast>,
+        ty: &CustomType<'ast>,
+        path: &str,
+    ) -> bool {
+        let (name, variant) = some_or!(path.split_once("::"), return false);
+        let translated = some_or!(inner.translated_types.get(ty), return false);
+        translated.items.iter().any(|item| {
+            item.name == name
+                && matches!(
+                    &item.sort,
+                    ItemSort::Type(t) if t.sort == TypeSort::Enum && t.variants.iter().any(|v| v == variant)
+                )
+        })
+    }
+
     fn make_types_transitive(
         &self,
         types: &mut Vec<CustomType<'ast>>,
@@ -688,12 +1690,72 @@ impl<'ast> Translator<'ast> {
         types.append(&mut trans);
     }
 
+    /// Returns the translated dependencies shown to the model, up to
+    /// `max_tokens`. When `estimating`, the C code of a dependency that is
+    /// not translated yet stands in for its translation.
+    fn translation_prefix(
+        &self,
+        mut types: Vec<CustomType<'ast>>,
+        vars: &[&'ast str],
+        funcs: &[&'ast str],
+        estimating: bool,
+    ) -> Vec<String> {
+        self.make_types_transitive(&mut types, vars, funcs);
+        let inner = self.inner.read().unwrap();
+        let deps = Self::dedup_items(inner.collect_dependencies(&types, vars, funcs));
+        let mut prefix: Vec<_> = deps.iter().map(|i| i.get_simple_code()).collect();
+        if estimating {
+            let types: BTreeSet<_> = types
+                .iter()
+                .filter(|ty| !inner.translated_types.contains_key(ty) && !self.is_struct_alias(ty))
+                .collect();
+            prefix.extend(
+                types
+                    .into_iter()
+                    .map(|ty| self.type_code(&inner, &[ty], true).0),
+            );
+            let vars: BTreeSet<_> = vars
+                .iter()
+                .filter(|var| !inner.translated_variables.contains_key(*var))
+                .collect();
+            for var in vars {
+                if let Some(var) = self.variables.get(var) {
+                    let vec =
+                        self.make_replace_vec(&inner, Some(&var.type_dependencies), None, None);
+                    prefix.push(self.program.variable_to_string(var, vec, true));
+                }
+            }
+            let funcs: BTreeSet<_> = funcs
+                .iter()
+                .filter(|func| !inner.translated_functions.contains_key(*func))
+                .collect();
+            for func in funcs {
+                if let Some(f) = self.functions.get(func) {
+                    let vec = self.make_replace_vec(&inner, Some(&f.type_dependencies), None, None);
+                    prefix.push(self.program.function_to_signature_string(f, vec));
+                } else if let Some(proto) = self.protos.get(func) {
+                    let vec =
+                        self.make_replace_vec(&inner, Some(&proto.type_dependencies), None, None);
+                    prefix.push(self.program.variable_to_string(proto, vec, true));
+                }
+            }
+        }
+        let mut tokens = 0;
+        prefix
+            .into_iter()
+            .take_while(|code| {
+                tokens += tokens_in_str(code);
+                tokens <= self.config.max_tokens
+            })
+            .collect()
+    }
+
     fn collect_dependencies(
         &self,
         name: &str,
         types: Option<&[TypeDependency<'ast>]>,
-        vars: Option<&[&'ast Node<Identifier>]>,
-        funcs: Option<&[&'ast Node<Identifier>]>,
+        vars: Option<&[TermDependency<'ast>]>,
+        funcs: Option<&[TermDependency<'ast>]>,
     ) -> DependencyPrefixes {
         tracing::info!(
             "collect before ({})\n{:?}\n{:?}\n{:?}",
@@ -702,48 +1764,20 @@ impl<'ast> Translator<'ast> {
             vars,
             funcs
         );
-        let mut types: Vec<_> = types.unwrap_or(&[]).iter().map(|x| x.typ).collect();
-        let mut vars: Vec<_> = vars
-            .unwrap_or(&[])
-            .iter()
-            .map(|x| x.node.name.as_str())
-            .collect();
-        let mut funcs: Vec<_> = funcs
-            .unwrap_or(&[])
-            .iter()
-            .map(|x| x.node.name.as_str())
-            .collect();
+        let types = types.unwrap_or(&[]).iter().map(|x| x.typ).collect();
+        let vars = vars.unwrap_or(&[]).iter().map(|x| x.name).collect();
+        let funcs = funcs.unwrap_or(&[]).iter().map(|x| x.name).collect();
+        self.collect_name_dependencies(name, types, vars, funcs)
+    }
 
-        let translation_prefix: Vec<String> = {
-            let mut types = types.clone();
-            self.make_types_transitive(&mut types, &vars, &funcs);
-            let inner = self.inner.read().unwrap();
-            let deps = Self::dedup_items(inner.collect_dependencies(&types, &vars, &funcs));
-            deps.iter().map(|i| i.get_simple_code()).collect()
-        };
-        let translation_prefix = if translation_prefix
-            .iter()
-            .map(|s| tokens_in_str(s))
-            .sum::<usize>()
-            <= self.config.max_tokens
-        {
-            translation_prefix
-        } else {
-            let inner = self.inner.read().unwrap();
-            let deps = Self::dedup_items(inner.collect_dependencies(&types, &vars, &funcs));
-
-            let mut tokens = 0;
-            let mut translation_prefix = vec![];
-            for i in &deps {
-                let code = i.get_simple_code();
-                tokens += tokens_in_str(&code);
-                if tokens > self.config.max_tokens {
-                    break;
-                }
-                translation_prefix.push(code);
-            }
-            translation_prefix
-        };
+    fn collect_name_dependencies(
+        &self,
+        name: &str,
+        mut types: Vec<CustomType<'ast>>,
+        mut vars: Vec<&'ast str>,
+        mut funcs: Vec<&'ast str>,
+    ) -> DependencyPrefixes {
+        let translation_prefix = self.translation_prefix(types.clone(), &vars, &funcs, false);
 
         let mut trans: Vec<_> = funcs
             .iter()
@@ -776,7 +1810,7 @@ impl<'ast> Translator<'ast> {
         );
         let inner = self.inner.read().unwrap();
         let deps = Self::dedup_items(inner.collect_dependencies(&types, &vars, &funcs));
-        let checking_prefix = std::iter::once(PREAMBLE.to_string())
+        let checking_prefix = std::iter::once(self.preamble.clone())
             .chain(deps.iter().map(|i| i.get_checking_code()))
             .chain(std::iter::once("fn main() {}".to_string()))
             .intersperse("\n".to_string())
@@ -839,7 +1873,7 @@ impl<'ast> Translator<'ast> {
             }
             *ctxt = new_ctxt;
             fixes += 1;
-            if fixes >= 20 {
+            if fixes >= ctxt.max_fixes {
                 break;
             }
         }
@@ -960,6 +1994,7 @@ impl<'ast> Translator<'ast> {
                         failed: false,
                         proto: false,
                         types: vec![],
+                        usage: TokenUsage::default(),
                     }
                     .code();
                     if ctxt.code == fix {
@@ -1032,17 +2067,17 @@ impl<'ast> Translator<'ast> {
         }
     }
 
-    pub async fn translate_names(&mut self) {
+    /// Pairs each type that is not renamed on its own with the typedef whose
+    /// new name it shares.
+    fn aliased_types(&self) -> Vec<(CustomType<'ast>, CustomType<'ast>)> {
         let mut aliased_map: BTreeMap<_, Vec<_>> = BTreeMap::new();
         for ty in &self.custom_types {
-            if ty.sort == TypeSort::Typedef {
+            if self.is_struct_alias(ty) {
                 let typedef = self.typedefs.get(ty.name).unwrap();
-                if typedef.is_struct_alias {
-                    aliased_map
-                        .entry(typedef.dependencies[0].typ)
-                        .or_default()
-                        .push(*ty);
-                }
+                aliased_map
+                    .entry(typedef.dependencies[0].typ)
+                    .or_default()
+                    .push(*ty);
             }
         }
         let mut aliased = vec![];
@@ -1053,6 +2088,19 @@ impl<'ast> Translator<'ast> {
                 aliased.push((ty, ty0));
             }
         }
+        aliased
+    }
+
+    fn is_struct_alias(&self, ty: &CustomType<'_>) -> bool {
+        ty.sort == TypeSort::Typedef && self.typedefs.get(ty.name).unwrap().is_struct_alias
+    }
+
+    pub async fn translate_names(&mut self) {
+        if !self.new_type_names.is_empty() || !self.new_term_names.is_empty() {
+            return;
+        }
+
+        let aliased = self.aliased_types();
         let alias_set: BTreeSet<_> = aliased.iter().map(|(ty, _)| *ty).collect();
         let custom_types: Vec<_> = self
             .custom_types
@@ -1060,11 +2108,9 @@ impl<'ast> Translator<'ast> {
             .filter(|ty| !alias_set.contains(ty))
             .collect();
 
-        let type_names = future::join_all(
-            custom_types
-                .iter()
-                .map(|ty| self.client.rename_type(ty.name)),
-        )
+        let type_names = future::join_all(custom_types.iter().map(|ty| {
+            llm_client::with_item(ty.name.to_string(), self.client.rename_type(ty.name))
+        }))
         .await;
 
         for (ty, new_name) in custom_types.into_iter().zip(type_names) {
@@ -1091,31 +2137,26 @@ impl<'ast> Translator<'ast> {
             self.new_type_names.insert(struct_ty, new_name);
         }
 
-        let var_names = future::join_all(
-            self.variables
-                .keys()
-                .map(|var| self.client.rename_variable(var)),
-        )
-        .await;
+        let var_names =
+            future::join_all(self.variables.keys().map(|var| {
+                llm_client::with_item(var.to_string(), self.client.rename_variable(var))
+            }))
+            .await;
         for (var, new_name) in self.variables.keys().zip(var_names) {
             self.new_term_names.insert(*var, new_name);
         }
 
-        let proto_names = future::join_all(
-            self.protos
-                .keys()
-                .map(|proto| self.client.rename_function(proto)),
-        )
+        let proto_names = future::join_all(self.protos.keys().map(|proto| {
+            llm_client::with_item(proto.to_string(), self.client.rename_function(proto))
+        }))
         .await;
         for (proto, new_name) in self.protos.keys().zip(proto_names) {
             self.new_term_names.insert(*proto, new_name);
         }
 
-        let func_names = future::join_all(
-            self.functions
-                .keys()
-                .map(|func| self.client.rename_function(func)),
-        )
+        let func_names = future::join_all(self.functions.keys().map(|func| {
+            llm_client::with_item(func.to_string(), self.client.rename_function(func))
+        }))
         .await;
         for (func, new_name) in self.functions.keys().zip(func_names) {
             let new_name = if KEYWORDS.contains(new_name.as_str()) {
@@ -1125,6 +2166,67 @@ impl<'ast> Translator<'ast> {
             };
             self.new_term_names.insert(*func, new_name);
         }
+
+        // Object-like macros keep their names, which are already constant names, and
+        // function-like macros are named like functions.
+        let function_macros: Vec<_> = self
+            .macros
+            .iter()
+            .filter(|(_, m)| m.is_function())
+            .map(|(name, _)| *name)
+            .collect();
+        let macro_names = future::join_all(function_macros.iter().map(|name| {
+            llm_client::with_item(name.to_string(), self.client.rename_function(name))
+        }))
+        .await;
+        let mut macro_names: BTreeMap<_, _> =
+            function_macros.into_iter().zip(macro_names).collect();
+        for name in self.macros.keys() {
+            let new_name = macro_names.remove(name).unwrap_or_else(|| name.to_string());
+            let mut new_name = if KEYWORDS.contains(new_name.as_str()) {
+                format!("my_{}", new_name)
+            } else {
+                new_name
+            };
+            while self.new_term_names.values().any(|n| n == &new_name)
+                || self.new_type_names.values().any(|n| n == &new_name)
+            {
+                new_name += "_macro";
+            }
+            self.new_term_names.insert(*name, new_name);
+        }
+        self.name_enumerators();
+        self.save_names();
+    }
+
+    /// Names each enumerator after the variant of the Rust enum translated from its type. The
+    /// variant names are given to the language model in the C definition of the enum.
+    fn name_enumerators(&mut self) {
+        let program = self.program;
+        for (enumerator, (sort, name)) in &program.enumerators {
+            let ty = CustomType {
+                name: name.as_str(),
+                sort: *sort,
+            };
+            let new_name = some_or!(self.new_type_names.get(&ty), continue);
+            let path = format!("{}::{}", new_name, variant_name(enumerator));
+            self.new_enumerator_names.insert(enumerator.as_str(), path);
+        }
+    }
+
+    /// Replaces the enumerators in a C enum definition with the names of the Rust variants.
+    fn variant_replacements<'a>(
+        &'a self,
+        enumerators: Vec<&Node<Identifier>>,
+    ) -> Vec<(Span, &'a str)> {
+        enumerators
+            .into_iter()
+            .filter_map(|e| {
+                let path = self.new_enumerator_names.get(e.node.name.as_str())?;
+                let variant = path.rsplit("::").next().unwrap();
+                Some((e.span, variant))
+            })
+            .collect()
     }
 
     async fn fix_types_after_translation(
@@ -1181,6 +2283,7 @@ impl<'ast> Translator<'ast> {
             &prefixes.checking_prefix,
             translated_code.clone(),
             &item_names,
+            self.config.max_fixes,
         );
         self.fix_by_llm(&mut ctxt, self.config.consider_stages, false)
             .await;
@@ -1264,13 +2367,33 @@ impl<'ast> Translator<'ast> {
         }
     }
 
-    async fn translate_type(&self, tys: Vec<&CustomType<'_>>) -> TranslationResult {
+    fn new_type_name<'a>(&'a self, ty: &CustomType<'a>) -> &'a str {
+        self.new_type_names
+            .get(ty)
+            .map_or(ty.name, |new_name| new_name.as_str())
+    }
+
+    fn new_term_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.new_term_names
+            .get(name)
+            .map_or(name, |new_name| new_name.as_str())
+    }
+
+    /// Returns the C code given to the model for types translated together,
+    /// its sort, the dependencies of the types and their new names. With
+    /// `pack_bit_fields`, bit-fields are replaced with their backing integers.
+    fn type_code(
+        &self,
+        inner: &TranslatorInner<'ast>,
+        tys: &[&CustomType<'ast>],
+        pack_bit_fields: bool,
+    ) -> (String, &'static str, Vec<TypeDependency<'ast>>, Vec<&str>) {
         let mut all_deps = vec![];
         let mut all_code = vec![];
         let mut sorts = BTreeSet::new();
         let mut new_names = vec![];
         for ty in tys {
-            let new_name = self.new_type_names.get(ty).unwrap().as_str();
+            let new_name = self.new_type_name(ty);
             new_names.push(new_name);
             match ty.sort {
                 TypeSort::Typedef => {
@@ -1279,7 +2402,8 @@ impl<'ast> Translator<'ast> {
                     let deps = &typedef.dependencies;
                     all_deps.append(&mut deps.clone());
 
-                    let vec = self.make_replace_vec(Some(deps), None, None);
+                    let mut vec = self.make_replace_vec(inner, Some(deps), None, None);
+                    vec.extend(self.variant_replacements(typedef.enumerators()));
                     let (code, sort) = match self
                         .program
                         .typedef_to_struct_string(typedef, vec, new_name)
@@ -1290,7 +2414,7 @@ impl<'ast> Translator<'ast> {
                             (self.program.typedef_to_string(typedef, vec), "type")
                         }
                     };
-                    all_code.push(code);
+                    all_code.push(self.with_comment(code, self.program.type_comment(ty)));
                     sorts.insert(sort);
                 }
                 TypeSort::Struct | TypeSort::Union => {
@@ -1298,29 +2422,66 @@ impl<'ast> Translator<'ast> {
                     let deps = &strct.dependencies;
                     all_deps.append(&mut deps.clone());
 
-                    let mut vec = self.make_replace_vec(Some(deps), None, None);
-                    vec.push((
-                        strct.struct_type.node.identifier.as_ref().unwrap().span,
-                        new_name,
-                    ));
+                    let bit_fields = strct.bit_fields.as_ref().filter(|_| pack_bit_fields);
+                    let backing =
+                        bit_fields.map_or(vec![], |bit_fields| bit_fields.backing_declarations());
+                    let mut vec = self.make_replace_vec(inner, Some(deps), None, None);
+                    if let Some(bit_fields) = bit_fields {
+                        vec.retain(|(span, _)| {
+                            !bit_fields
+                                .runs
+                                .iter()
+                                .any(|(run, _)| c_parser::overlap(*span, *run))
+                        });
+                    }
+                    vec.extend(backing.iter().map(|(span, decls)| (*span, decls.as_str())));
+                    let (span, tag) = strct.tag(new_name);
+                    vec.push((span, tag.as_str()));
                     let code = self.program.struct_to_string(strct, vec);
                     let sort = if strct.strct { "struct" } else { "union" };
-                    all_code.push(code);
+                    all_code.push(self.with_comment(code, self.program.type_comment(ty)));
                     sorts.insert(sort);
                 }
                 TypeSort::Enum => {
                     let enm = self.enums.get(ty.name).unwrap();
-                    let vec = vec![(
-                        enm.enum_type.node.identifier.as_ref().unwrap().span,
-                        new_name,
-                    )];
+                    let (span, tag) = enm.tag(new_name);
+                    let mut vec = vec![(span, tag.as_str())];
+                    vec.extend(self.variant_replacements(enm.enumerators()));
                     let code = self.program.enum_to_string(enm, vec);
-                    all_code.push(code);
+                    all_code.push(self.with_comment(code, self.program.type_comment(ty)));
                     sorts.insert("enum");
                 }
             }
         }
+        let sort = if sorts.len() == 1 {
+            sorts.into_iter().next().unwrap()
+        } else {
+            "type"
+        };
+        (all_code.join("\n"), sort, all_deps, new_names)
+    }
+
+    async fn translate_type(&self, tys: Vec<&CustomType<'ast>>) -> TranslationResult {
+        let (translated, packed) = self.translate_type_code(&tys, true).await;
+        if packed {
+            return translated;
+        }
+        let new_names: Vec<_> = tys.iter().map(|ty| self.new_type_name(ty)).collect();
+        if !self.config.quiet {
+            println!("Bit-fields not packed: {:?}", new_names);
+        }
+        self.translate_type_code(&tys, false).await.0
+    }
 
+    /// Translates types together and returns whether the accessors of their
+    /// packed bit-fields, if any, are all added.
+    async fn translate_type_code(
+        &self,
+        tys: &[&CustomType<'ast>],
+        pack_bit_fields: bool,
+    ) -> (TranslationResult, bool) {
+        let (code, sort, all_deps, new_names) =
+            self.type_code(&self.inner.read().unwrap(), tys, pack_bit_fields);
         let prefixes = self.collect_dependencies(new_names[0], Some(&all_deps), None, None);
         tracing::info!(
             "translate_type translation_prefix ({:?})\n{}",
@@ -1328,18 +2489,12 @@ impl<'ast> Translator<'ast> {
             prefixes.translation_prefix.join("\n")
         );
 
-        let code = all_code.join("\n");
         tracing::info!(
             "translate_type translation_prefix ({:?})\n{}",
             new_names,
             code,
         );
 
-        let sort = if sorts.len() == 1 {
-            sorts.into_iter().next().unwrap()
-        } else {
-            "type"
-        };
         let translated = if tokens_in_str(&code) > self.config.max_tokens * 2 {
             "".to_string()
         } else {
@@ -1382,13 +2537,98 @@ impl<'ast> Translator<'ast> {
             failed: false,
             proto: false,
             types: vec![],
+            usage: TokenUsage::default(),
         };
-        self.fix_types_after_translation(new_names, translated, prefixes)
-            .await
+        let accessors = tys
+            .iter()
+            .filter(|ty| pack_bit_fields && ty.sort == TypeSort::Struct)
+            .filter_map(|ty| {
+                let bit_fields = self.structs.get(ty.name)?.bit_fields.as_ref()?;
+                let new_name = self.new_type_name(ty);
+                Some((new_name, bit_field_accessors(new_name, bit_fields)))
+            })
+            .collect();
+        let checking_prefix = prefixes.checking_prefix.clone();
+        let mut translated = self
+            .fix_types_after_translation(new_names, translated, prefixes)
+            .await;
+        let added = self.add_bit_field_accessors(&mut translated, accessors, &checking_prefix);
+        (translated, added)
+    }
+
+    /// Appends the accessors of bit-fields to the translated structs. Returns
+    /// `false` if a struct is missing or its accessors do not type-check
+    /// against the backing integers.
+    fn add_bit_field_accessors(
+        &self,
+        translated: &mut TranslationResult,
+        accessors: Vec<(&str, String)>,
+        checking_prefix: &str,
+    ) -> bool {
+        for (name, code) in accessors {
+            let i = some_or!(
+                translated.items.iter().position(|item| {
+                    item.name == name
+                        && matches!(&item.sort, ItemSort::Type(t) if t.sort == TypeSort::Struct)
+                }),
+                return false
+            );
+            let item = &mut translated.items[i];
+            item.code = format!("{}\n{}", item.code, code);
+            let code = format!("{}\n{}", checking_prefix, translated.code());
+            if !compiler::type_check(&code).map_or(false, |res| res.passed()) {
+                tracing::info!("add_bit_field_accessors rejected ({})\n{}", name, code);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Describes the bit-field accessors of the structs used by a function.
+    fn bit_field_note(&self, func: &Function<'ast>) -> Option<String> {
+        let mut types: Vec<_> = func.type_dependencies.iter().map(|d| d.typ).collect();
+        self.make_types_transitive(&mut types, &[], &[]);
+        let inner = self.inner.read().unwrap();
+        let notes: BTreeSet<_> = types
+            .iter()
+            .filter(|ty| ty.sort == TypeSort::Struct)
+            .filter_map(|ty| {
+                let bit_fields = self.structs.get(ty.name)?.bit_fields.as_ref()?;
+                let new_name = self.new_type_names.get(ty)?;
+                let accessors = bit_field_accessors(new_name, bit_fields);
+                let translated = inner.translated_types.get(ty)?;
+                if !translated.items.iter().any(|i| i.code.contains(&accessors)) {
+                    return None;
+                }
+                let methods = bit_fields
+                    .fields
+                    .iter()
+                    .map(|f| {
+                        let getter = bit_field_getter(&f.name);
+                        format!("`{}()`/`set_{}(value)`", getter, f.name)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                Some(format!(
+                    "The bit-fields of `{}` are accessed with {}.",
+                    new_name, methods
+                ))
+            })
+            .collect();
+        if notes.is_empty() {
+            None
+        } else {
+            Some(notes.into_iter().collect::<Vec<_>>().join("\n"))
+        }
     }
 
     pub async fn translate_types(&self) {
         let mut graph = self.type_graph.clone();
+        if let Some(selected) = &self.selected {
+            prune_graph(&mut graph, &self.type_elem_map, |ty| {
+                selected.types.contains(ty)
+            });
+        }
         let mut futures = vec![];
 
         loop {
@@ -1397,6 +2637,16 @@ impl<'ast> Translator<'ast> {
                 .map(|(id, _)| self.type_elem_map.get(&id).unwrap())
                 .map(|set| {
                     async {
+                        let restored = self
+                            .inner
+                            .read()
+                            .unwrap()
+                            .translated_types
+                            .get(set.first().unwrap())
+                            .cloned();
+                        if let Some(translated) = restored {
+                            return (set.iter().collect::<Vec<_>>(), translated);
+                        }
                         let (non_aliases, mut aliases): (Vec<_>, _) = set
                             .iter()
                             .map(|ty| {
@@ -1415,9 +2665,20 @@ impl<'ast> Translator<'ast> {
                             assert!(aliases.len() == 1);
                             let inner = self.inner.read().unwrap();
                             let (_, dep) = aliases.pop().unwrap();
-                            inner.translated_types.get(&dep.unwrap()).unwrap().clone()
+                            let mut translated =
+                                inner.translated_types.get(&dep.unwrap()).unwrap().clone();
+                            // the requests belong to the aliased type
+                            translated.usage = TokenUsage::default();
+                            translated
                         } else {
-                            self.translate_type(non_aliases).await
+                            {
+                                let item = non_aliases
+                                    .iter()
+                                    .map(|ty| ty.name)
+                                    .collect::<Vec<_>>()
+                                    .join(", ");
+                                track_usage(item, self.translate_type(non_aliases)).await
+                            }
                         };
                         (set.iter().collect::<Vec<_>>(), translated)
                     }
@@ -1450,9 +2711,50 @@ impl<'ast> Translator<'ast> {
 
             let mut inner = self.inner.write().unwrap();
             inner.add_names(&translated);
+            let mut translated = translated;
             for ty in tys {
+                self.save_type(ty, &translated);
                 inner.translated_types.insert(*ty, translated.clone());
+                // types translated together share their requests, which are
+                // attributed to the first one only
+                translated.usage = TokenUsage::default();
+            }
+        }
+    }
+
+    /// Returns the C code given to the model for a variable and whether only
+    /// its declaration fits.
+    fn variable_code(&self, var: &Variable<'ast>, new_name: &str) -> (String, bool) {
+        let tdeps = &var.type_dependencies;
+        let deps = &var.dependencies;
+        let functions: Vec<_> = var.function_pointers.iter().map(|u| u.function).collect();
+        let mut vec = self.make_replace_vec(
+            &self.inner.read().unwrap(),
+            Some(tdeps),
+            Some(deps),
+            Some(&functions),
+        );
+        vec.push((var.identifier.span, new_name));
+        let code = self.program.variable_to_string(var, vec.clone(), false);
+        let (code, too_long) = if tokens_in_str(&code) > self.config.max_tokens {
+            (self.program.variable_to_string(var, vec, true), true)
+        } else {
+            (code, false)
+        };
+        (
+            self.with_comment(code, self.program.term_comment(var.name)),
+            too_long,
+        )
+    }
+
+    /// Prepends the comment of a C declaration to its code given to the language model when
+    /// comments are enabled in the prompts.
+    fn with_comment(&self, code: String, comment: Option<&str>) -> String {
+        match comment {
+            Some(comment) if self.config.prompt_comments => {
+                format!("{}\n{}", comment_lines(comment, "//"), code)
             }
+            _ => code,
         }
     }
 
@@ -1465,15 +2767,7 @@ impl<'ast> Translator<'ast> {
         let deps = &var.dependencies;
         let prefixes = self.collect_dependencies(new_name, Some(tdeps), Some(deps), None);
 
-        let mut vec = self.make_replace_vec(Some(tdeps), Some(deps), None);
-        vec.push((var.identifier.span, new_name));
-        let code = self.program.variable_to_string(var, vec.clone(), false);
-        let too_long = tokens_in_str(&code) > self.config.max_tokens;
-        let code = if too_long {
-            self.program.variable_to_string(var, vec, true)
-        } else {
-            code
-        };
+        let (code, too_long) = self.variable_code(var, new_name);
         tracing::info!(
             "translate_variable code ({})\ntoo_long: {}\n{}",
             new_name,
@@ -1554,6 +2848,7 @@ impl<'ast> Translator<'ast> {
             failed: false,
             proto: false,
             types: vec![],
+            usage: TokenUsage::default(),
         };
         tracing::info!(
             "translate_variable translated ({})\n{}",
@@ -1572,6 +2867,7 @@ impl<'ast> Translator<'ast> {
             &prefixes.checking_prefix,
             translated_code.clone(),
             &item_names,
+            self.config.max_fixes,
         );
         if self.config.fix_errors {
             self.fix_by_llm(&mut ctxt, self.config.consider_stages, false)
@@ -1597,6 +2893,7 @@ impl<'ast> Translator<'ast> {
             &prefixes.checking_prefix,
             translated.checking_code(),
             &item_names,
+            self.config.max_fixes,
         );
         if !ctxt2.result.unwrap().passed() {
             let code = on_failure();
@@ -1629,6 +2926,11 @@ impl<'ast> Translator<'ast> {
 
     pub async fn translate_variables(&self) {
         let mut graph = self.variable_graph.clone();
+        if let Some(selected) = &self.selected {
+            prune_graph(&mut graph, &self.variable_elem_map, |var| {
+                selected.variables.contains(var)
+            });
+        }
         let mut futures = vec![];
 
         loop {
@@ -1638,8 +2940,18 @@ impl<'ast> Translator<'ast> {
                 .map(|set| {
                     async {
                         let var = *set.first().unwrap();
+                        let restored = self
+                            .inner
+                            .read()
+                            .unwrap()
+                            .translated_variables
+                            .get(var)
+                            .cloned();
+                        if let Some(translated) = restored {
+                            return (var, translated);
+                        }
                         let translated = if set.len() == 1 {
-                            self.translate_variable(var).await
+                            track_usage(var.to_string(), self.translate_variable(var)).await
                         } else {
                             let code = set
                                 .iter()
@@ -1655,6 +2967,7 @@ impl<'ast> Translator<'ast> {
                                 failed: false,
                                 proto: false,
                                 types: vec![],
+                                usage: TokenUsage::default(),
                             }
                         };
                         (var, translated)
@@ -1682,57 +2995,408 @@ impl<'ast> Translator<'ast> {
 
             let mut inner = self.inner.write().unwrap();
             inner.add_names(&translated);
+            self.save_term("variables", var, &translated);
             inner.translated_variables.insert(var, translated);
         }
     }
 
-    async fn translate_proto(&self, name: &str) -> TranslationResult {
-        let proto = self.protos.get(name).unwrap();
-        let new_name = self.new_term_names.get(name).unwrap();
-        tracing::info!("translate_proto: {}", new_name);
+    /// Returns the types, the variables and macros, and the prototypes a macro refers to.
+    fn macro_dependencies(
+        &self,
+        m: &'ast Macro,
+    ) -> (Vec<CustomType<'ast>>, Vec<&'ast str>, Vec<&'ast str>) {
+        let mut types = vec![];
+        let mut vars = vec![];
+        let mut funcs = vec![];
+        for (tag, id) in m.identifiers() {
+            match tag {
+                Some("enum") => {
+                    if let Some((name, _)) = self.enums.get_key_value(id) {
+                        types.push(CustomType::mk_enum(name));
+                    }
+                }
+                Some(_) => {
+                    if let Some((name, s)) = self.structs.get_key_value(id) {
+                        types.push(if s.strct {
+                            CustomType::mk_struct(name)
+                        } else {
+                            CustomType::mk_union(name)
+                        });
+                    }
+                }
+                None => {
+                    if let Some((name, _)) = self.macros.get_key_value(id) {
+                        vars.push(*name);
+                    } else if let Some((name, _)) = self.variables.get_key_value(id) {
+                        vars.push(*name);
+                    } else if let Some((name, _)) = self.protos.get_key_value(id) {
+                        funcs.push(*name);
+                    } else if let Some((name, _)) = self.typedefs.get_key_value(id) {
+                        types.push(CustomType::mk_typedef(name));
+                    } else if let Some((sort, name)) = self.program.enumerators.get(id) {
+                        types.push(CustomType {
+                            name: name.as_str(),
+                            sort: *sort,
+                        });
+                    }
+                }
+            }
+        }
+        (types, vars, funcs)
+    }
 
-        let tdeps = &proto.type_dependencies;
-        let deps = &proto.dependencies;
-        let mut vec = self.make_replace_vec(Some(tdeps), Some(deps), None);
-        vec.push((proto.identifier.span, new_name));
-        let code = self.program.variable_to_string(proto, vec, false);
-        tracing::info!("translate_proto code ({})\n{}", new_name, code);
+    async fn translate_macro(&self, name: &str) -> TranslationResult {
+        let m = self.macros.get(name).unwrap();
+        let new_name = self.new_term_names.get(name).unwrap();
+        tracing::info!("translate_macro: {}", new_name);
 
-        let prefixes = self.collect_dependencies(new_name, Some(tdeps), Some(deps), None);
-        let empty = vec![];
-        let translation_prefix = if self.config.provide_signatures {
-            &prefixes.translation_prefix
+        let code = m.code();
+        let sort = if m.is_function() {
+            "function"
         } else {
-            &empty
+            "constant"
         };
+        let (types, vars, funcs) = self.macro_dependencies(m);
+        let prefixes = self.collect_name_dependencies(new_name, types, vars, funcs);
+        let translated = self
+            .client
+            .translate_macro(&code, sort, new_name, &prefixes.translation_prefix)
+            .await;
         tracing::info!(
-            "translate_proto translation_prefix ({})\n{}",
-            new_name,
-            translation_prefix.join("\n")
-        );
-        tracing::info!(
-            "translate_proto checking_prefix ({})\n{}",
+            "translate_macro translated ({})\n{:?}",
             new_name,
-            prefixes.checking_prefix
+            translated
         );
 
-        let sig_map = self.translate_signature(&code, new_name, &prefixes).await;
-        let sig = sig_map
-            .into_iter()
-            .next()
-            .map(|(_, sig)| sig)
-            .unwrap_or_else(|| {
-                if !self.config.quiet {
-                    println!("Proto not translated: {}", new_name);
-                }
-                format!("fn {}()", new_name)
+        let item = translated
+            .and_then(|translated| compiler::parse(&translated))
+            .and_then(|mut items| {
+                let uses = Self::take_uses(&mut items);
+                let item = items.into_iter().find(|item| {
+                    item.name == *new_name
+                        && match item.sort {
+                            ItemSort::Variable(_) => !m.is_function(),
+                            ItemSort::Function(_) => m.is_function(),
+                            _ => false,
+                        }
+                })?;
+                let translated = compiler::resolve_imports(&item.get_code(), &uses.join(""))?;
+                compiler::parse_one(&translated)
             });
-        let translated = format!("{}{{todo!(\"proto\")}}", sig);
-        tracing::info!("translate_proto result ({})\n{}", new_name, translated);
-
-        if !self.config.quiet {
-            println!("proto: {}", new_name);
-        }
+        let mut translated = TranslationResult {
+            items: item.into_iter().collect(),
+            stage: compiler::MAX_STAGE,
+            errors: 0,
+            too_long: false,
+            failed: false,
+            proto: false,
+            types: vec![],
+            usage: TokenUsage::default(),
+        };
+
+        if translated.items.is_empty() {
+            translated.failed = true;
+        } else {
+            let item_names: BTreeSet<_> = translated.items.iter().map(|i| i.name.clone()).collect();
+            let translated_code = translated.code();
+            let mut ctxt = FixContext::new(
+                &prefixes.checking_prefix,
+                translated_code.clone(),
+                &item_names,
+                self.config.max_fixes,
+            );
+            if self.config.fix_errors {
+                self.fix_by_llm(&mut ctxt, self.config.consider_stages, m.is_function())
+                    .await;
+                if translated_code != ctxt.code {
+                    translated.items = compiler::parse(&ctxt.code).unwrap();
+                }
+            }
+            let result = ctxt.result.unwrap();
+            translated.stage = result.stage;
+            translated.errors = result.errors.len();
+            // Functions only refer to macros translated without errors.
+            if translated.errors > 0 {
+                translated.items.clear();
+                translated.failed = true;
+            }
+        }
+
+        tracing::info!(
+            "translate_macro result ({})\n{}",
+            new_name,
+            translated.code()
+        );
+        if !self.config.quiet {
+            if translated.failed {
+                println!("Macro not translated: {}", new_name);
+            } else {
+                println!("macro: {}", new_name);
+            }
+        }
+
+        translated
+    }
+
+    /// Translates the macros after the macros they use, and logs the macros that are skipped.
+    pub async fn translate_macros(&self) {
+        if self.config.recover_macros {
+            for (name, reason) in &self.program.skipped_macros {
+                tracing::info!("macro skipped: {} ({})", name, reason);
+                if !self.config.quiet {
+                    println!("Macro skipped: {} ({})", name, reason);
+                }
+            }
+        }
+        let mut macros: BTreeSet<_> = {
+            let inner = self.inner.read().unwrap();
+            self.macros
+                .keys()
+                .filter(|name| !inner.translated_macros.contains_key(*name))
+                .filter(|name| {
+                    self.selected
+                        .as_ref()
+                        .map_or(true, |s| s.macros.contains(*name))
+                })
+                .copied()
+                .collect()
+        };
+        while !macros.is_empty() {
+            let ready: Vec<_> = macros
+                .iter()
+                .copied()
+                .filter(|name| {
+                    let (_, vars, _) = self.macro_dependencies(self.macros[name]);
+                    vars.iter().all(|v| *v == *name || !macros.contains(v))
+                })
+                .collect();
+            // The macros on a cycle are translated together.
+            let ready = if ready.is_empty() {
+                macros.iter().copied().collect()
+            } else {
+                ready
+            };
+            for name in &ready {
+                macros.remove(name);
+            }
+            let translated = future::join_all(ready.into_iter().map(|name| {
+                async move {
+                    (
+                        name,
+                        track_usage(name.to_string(), self.translate_macro(name)).await,
+                    )
+                }
+                .boxed()
+            }))
+            .await;
+            for (name, translated) in translated {
+                let mut inner = self.inner.write().unwrap();
+                inner.add_names(&translated);
+                self.save_term("macros", name, &translated);
+                inner.translated_macros.insert(name, translated);
+            }
+        }
+    }
+
+    /// Callees and functions used as function pointers.
+    fn function_references(&self, func: &Function<'ast>) -> Vec<TermDependency<'ast>> {
+        let functions = func.function_pointers.iter().map(|u| &u.function);
+        func.callees.iter().chain(functions).copied().collect()
+    }
+
+    fn function_pointer_note(&self, name: &str) -> Option<String> {
+        let contexts = self.function_pointer_contexts.get(name)?;
+        let new_name = self.new_term_names.get(name).unwrap();
+        Some(format!(
+            "`{}` is used as a function pointer: {}. \
+             Its signature must be compatible with these uses.",
+            new_name,
+            contexts
+                .iter()
+                .map(|c| c.as_str())
+                .collect::<Vec<_>>()
+                .join("; ")
+        ))
+    }
+
+    /// Checks if a function or a prototype takes variable arguments.
+    fn is_variadic(&self, name: &str) -> bool {
+        match self.functions.get(name) {
+            Some(func) => func.type_signature.variadic,
+            None => self.protos.get(name).map_or(false, |proto| {
+                c_parser::is_variadic(&proto.declarator.node.declarator.node)
+            }),
+        }
+    }
+
+    /// Returns the callees of a function that take their variable arguments as `&[VaArg]`,
+    /// including the pointers to variadic functions it calls through.
+    fn variadic_callees(&self, func: &Function<'ast>) -> BTreeSet<&str> {
+        let callees = func
+            .callees
+            .iter()
+            .filter(|c| self.is_variadic(c.name))
+            .filter_map(|c| self.new_term_names.get(c.name))
+            .map(|s| s.as_str());
+        let pointers = func
+            .variadic_indirect_calls
+            .iter()
+            .map(|c| self.new_term_names.get(*c).map_or(*c, |s| s.as_str()));
+        callees.chain(pointers).collect()
+    }
+
+    /// Checks if the translation of a function needs the definition of `VaArg`.
+    fn uses_va_args(&self, func: &Function<'ast>) -> bool {
+        func.type_signature.variadic || !self.variadic_callees(func).is_empty()
+    }
+
+    /// Describes how the variable arguments of a function and of its variadic callees are passed.
+    fn variadic_note(&self, func: &Function<'ast>) -> Option<String> {
+        let mut notes = vec![];
+        if func.type_signature.variadic {
+            notes.push(VARIADIC_NOTE.to_string());
+        }
+        for callee in self.variadic_callees(func) {
+            notes.push(format!(
+                "`{}` takes its variable arguments as a last argument of type `&[VaArg]`, \
+                 e.g., `&[VaArg::Int(n as i64), VaArg::Ptr(s as *mut libc::c_void)]`.",
+                callee
+            ));
+        }
+        if notes.is_empty() {
+            None
+        } else {
+            Some(notes.join("\n"))
+        }
+    }
+
+    /// Returns the dependencies of a function, including the uses of the macros translated
+    /// without errors.
+    fn function_dependencies(&self, func: &Function<'ast>) -> Vec<TermDependency<'ast>> {
+        let inner = self.inner.read().unwrap();
+        let macros = func
+            .macros
+            .iter()
+            .filter(|m| Self::is_translated_macro(&inner, m.name))
+            .map(|m| TermDependency {
+                name: m.name,
+                span: m.span,
+            });
+        func.dependencies.iter().copied().chain(macros).collect()
+    }
+
+    fn is_translated_macro(inner: &TranslatorInner<'ast>, name: &str) -> bool {
+        inner
+            .translated_macros
+            .get(name)
+            .map_or(false, |t| !t.failed)
+    }
+
+    /// Replaces the expansions of the macros translated without errors with their uses. The
+    /// replacements inside the arguments of a function-like macro are kept, and an expansion is
+    /// left as it is when a replacement crosses its bounds.
+    fn replace_macro_uses(&self, func: &Function<'ast>, vec: &mut Vec<(Span, String)>) {
+        let inner = self.inner.read().unwrap();
+        let mut replaced: Vec<Span> = vec![];
+        for u in &func.macros {
+            if !Self::is_translated_macro(&inner, u.name)
+                || replaced.iter().any(|s| c_parser::overlap(*s, u.span))
+            {
+                continue;
+            }
+            let new_name = &self.new_term_names[u.name];
+            let mut bounds = vec![u.span.start];
+            bounds.extend(u.args.iter().flat_map(|a| [a.start, a.end]));
+            bounds.push(u.span.end);
+            let pieces: Vec<_> = bounds
+                .chunks(2)
+                .enumerate()
+                .map(|(i, b)| {
+                    let s = if !self.macros[u.name].is_function() {
+                        new_name.clone()
+                    } else if u.args.is_empty() {
+                        format!("{}()", new_name)
+                    } else if i == 0 {
+                        format!("{}(", new_name)
+                    } else if i == u.args.len() {
+                        ")".to_string()
+                    } else {
+                        ", ".to_string()
+                    };
+                    (Span::span(b[0], b[1]), s)
+                })
+                .collect();
+            let within = |s: &Span, t: &Span| t.start <= s.start && s.end <= t.end;
+            let in_piece = |s: &Span| pieces.iter().any(|(p, _)| within(s, p));
+            let crosses = |s: &Span| {
+                c_parser::overlap(*s, u.span)
+                    && !in_piece(s)
+                    && !u.args.iter().any(|a| within(s, a))
+            };
+            if vec.iter().any(|(s, _)| crosses(s)) {
+                continue;
+            }
+            vec.retain(|(s, _)| !in_piece(s));
+            vec.extend(pieces);
+            replaced.push(u.span);
+        }
+    }
+
+    async fn translate_proto(&self, name: &str) -> TranslationResult {
+        let proto = self.protos.get(name).unwrap();
+        let new_name = self.new_term_names.get(name).unwrap();
+        tracing::info!("translate_proto: {}", new_name);
+
+        let tdeps = &proto.type_dependencies;
+        let deps = &proto.dependencies;
+        let mut vec =
+            self.make_replace_vec(&self.inner.read().unwrap(), Some(tdeps), Some(deps), None);
+        vec.push((proto.identifier.span, new_name));
+        let code = self.program.variable_to_string(proto, vec, false);
+        let variadic = self.is_variadic(name);
+        let code = prepend_note(code, variadic.then(|| VARIADIC_NOTE.to_string()));
+        tracing::info!("translate_proto code ({})\n{}", new_name, code);
+
+        let mut prefixes = self.collect_dependencies(new_name, Some(tdeps), Some(deps), None);
+        if variadic {
+            prefixes.translation_prefix.insert(0, VA_ARG.to_string());
+        }
+        let empty = vec![];
+        let translation_prefix = if self.config.provide_signatures {
+            &prefixes.translation_prefix
+        } else {
+            &empty
+        };
+        tracing::info!(
+            "translate_proto translation_prefix ({})\n{}",
+            new_name,
+            translation_prefix.join("\n")
+        );
+        tracing::info!(
+            "translate_proto checking_prefix ({})\n{}",
+            new_name,
+            prefixes.checking_prefix
+        );
+
+        let sig_map = self
+            .translate_signature(&code, new_name, &prefixes, variadic)
+            .await;
+        let sig = sig_map
+            .into_iter()
+            .next()
+            .map(|(_, sig)| sig)
+            .unwrap_or_else(|| {
+                if !self.config.quiet {
+                    println!("Proto not translated: {}", new_name);
+                }
+                format!("fn {}()", new_name)
+            });
+        let translated = format!("{}{{todo!(\"proto\")}}", sig);
+        tracing::info!("translate_proto result ({})\n{}", new_name, translated);
+
+        if !self.config.quiet {
+            println!("proto: {}", new_name);
+        }
         TranslationResult {
             items: vec![compiler::parse_one(&translated).unwrap()],
             stage: compiler::MAX_STAGE,
@@ -1741,37 +3405,126 @@ impl<'ast> Translator<'ast> {
             failed: false,
             proto: true,
             types: vec![],
+            usage: TokenUsage::default(),
         }
     }
 
     pub async fn translate_protos(&self) {
-        let translated = future::join_all(
+        let protos: Vec<_> = {
+            let inner = self.inner.read().unwrap();
             self.protos
                 .keys()
-                .map(|name| async { (*name, self.translate_proto(name).await) }.boxed()),
-        )
+                .filter(|name| !inner.translated_functions.contains_key(*name))
+                .filter(|name| {
+                    self.selected
+                        .as_ref()
+                        .map_or(true, |s| s.protos.contains(*name))
+                })
+                .collect()
+        };
+        let translated = future::join_all(protos.into_iter().map(|name| {
+            async {
+                (
+                    *name,
+                    track_usage(name.to_string(), self.translate_proto(name)).await,
+                )
+            }
+            .boxed()
+        }))
         .await;
         for (name, translated) in translated {
             let mut inner = self.inner.write().unwrap();
             inner.add_names(&translated);
+            self.save_term("functions", name, &translated);
             inner.translated_functions.insert(name, translated);
         }
     }
 
-    async fn translate_function(
-        &self,
-        name: &str,
-        target_sig: Option<&FunctionInfo>,
-        prev: Option<TranslationResult>,
-    ) -> TranslationResult {
+    async fn translate_stub(&self, name: &str) -> TranslationResult {
         let func = self.functions.get(name).unwrap();
         let new_name = self.new_term_names.get(name).unwrap();
-        tracing::info!("translate_function: {}", new_name);
+        tracing::info!("translate_stub: {}", new_name);
 
+        let tdeps = &func.type_dependencies;
+        let mut vec = self.make_replace_vec(&self.inner.read().unwrap(), Some(tdeps), None, None);
+        vec.push((func.identifier.span, new_name));
+        let code = self.program.function_to_signature_string(func, vec);
+        let variadic = func.type_signature.variadic;
+        let code = prepend_note(code, variadic.then(|| VARIADIC_NOTE.to_string()));
+        tracing::info!("translate_stub code ({})\n{}", new_name, code);
+
+        let mut prefixes = self.collect_dependencies(new_name, Some(tdeps), None, None);
+        if variadic {
+            prefixes.translation_prefix.insert(0, VA_ARG.to_string());
+        }
+        let sig = if let Some(sig) = self.main_signature(name) {
+            sig
+        } else {
+            let sig_map = self
+                .translate_signature(&code, new_name, &prefixes, variadic)
+                .await;
+            sig_map
+                .into_values()
+                .next()
+                .unwrap_or_else(|| format!("fn {}()", new_name))
+        };
+        let translated = format!("{}{{todo!()}}", sig);
+        tracing::info!("translate_stub result ({})\n{}", new_name, translated);
+
+        if !self.config.quiet {
+            println!("stub: {}", new_name);
+        }
+        TranslationResult {
+            items: vec![compiler::parse_one(&translated).unwrap()],
+            stage: compiler::MAX_STAGE,
+            errors: 0,
+            too_long: false,
+            failed: false,
+            proto: true,
+            types: vec![],
+            usage: TokenUsage::default(),
+        }
+    }
+
+    async fn translate_stubs(&self) {
+        let selected = some_or!(&self.selected, return);
+        let stubs: Vec<_> = {
+            let inner = self.inner.read().unwrap();
+            selected
+                .stubs
+                .iter()
+                .filter(|name| !inner.translated_functions.contains_key(*name))
+                .collect()
+        };
+        let translated = future::join_all(stubs.into_iter().map(|name| {
+            async {
+                (
+                    *name,
+                    track_usage(name.to_string(), self.translate_stub(name)).await,
+                )
+            }
+            .boxed()
+        }))
+        .await;
+        for (name, translated) in translated {
+            let mut inner = self.inner.write().unwrap();
+            inner.add_names(&translated);
+            inner.translated_functions.insert(name, translated);
+        }
+    }
+
+    /// Returns the C code given to the model for a function and whether only
+    /// its signature fits.
+    fn function_code(&self, func: &Function<'ast>, new_name: &str) -> (String, bool) {
         let tdeps = &func.type_dependencies;
         let deps = &func.dependencies;
-        let callees = &func.callees;
-        let mut vec = self.make_replace_vec(Some(tdeps), Some(deps), Some(callees));
+        let callees = &self.function_references(func);
+        let mut vec = self.make_replace_vec(
+            &self.inner.read().unwrap(),
+            Some(tdeps),
+            Some(deps),
+            Some(callees),
+        );
         let in_spans = c_parser::find_names(func.definition, "in");
         for span in in_spans {
             if !vec.iter().any(|(s, _)| *s == span) {
@@ -1779,13 +3532,37 @@ impl<'ast> Translator<'ast> {
             }
         }
         vec.push((func.identifier.span, new_name));
-        let code = self.program.function_to_string(func, vec.clone());
-        let too_long = tokens_in_str(&code) > self.config.max_tokens;
-        let code = if too_long {
-            self.program.function_to_signature_string(func, vec.clone())
+        let mut vec: Vec<_> = vec.into_iter().map(|(s, x)| (s, x.to_string())).collect();
+        self.replace_macro_uses(func, &mut vec);
+        let code = self
+            .program
+            .function_to_string(func, vec.iter().map(|(s, x)| (*s, x.as_str())).collect());
+        let (code, too_long) = if tokens_in_str(&code) > self.config.max_tokens {
+            (self.program.function_to_signature_string(func, vec), true)
         } else {
-            code
+            (code, false)
         };
+        let code = self.with_comment(code, self.program.term_comment(func.name));
+        let code = prepend_note(code, self.bit_field_note(func));
+        let code = prepend_note(code, self.function_pointer_note(func.name));
+        let code = prepend_note(code, self.variadic_note(func));
+        (code, too_long)
+    }
+
+    async fn translate_function(
+        &self,
+        name: &str,
+        target_sig: Option<&FunctionInfo>,
+        prev: Option<TranslationResult>,
+    ) -> TranslationResult {
+        let func = self.functions.get(name).unwrap();
+        let new_name = self.new_term_names.get(name).unwrap();
+        tracing::info!("translate_function: {}", new_name);
+
+        let tdeps = &func.type_dependencies;
+        let deps = &self.function_dependencies(func);
+        let callees = &self.function_references(func);
+        let (code, too_long) = self.function_code(func, new_name);
         tracing::info!(
             "translate_function code ({})\ntoo_long: {}\n{}",
             new_name,
@@ -1793,7 +3570,11 @@ impl<'ast> Translator<'ast> {
             code
         );
 
-        let prefixes = self.collect_dependencies(new_name, Some(tdeps), Some(deps), Some(callees));
+        let mut prefixes =
+            self.collect_dependencies(new_name, Some(tdeps), Some(deps), Some(callees));
+        if self.uses_va_args(func) {
+            prefixes.translation_prefix.insert(0, VA_ARG.to_string());
+        }
         tracing::info!(
             "translate_function translation_prefix ({})\n{}",
             new_name,
@@ -1805,7 +3586,20 @@ impl<'ast> Translator<'ast> {
             prefixes.checking_prefix
         );
 
-        let translated = if let Some(target_sig) = target_sig {
+        let main_sig = self.main_signature(name);
+        let translated = if let Some(main_sig) = &main_sig {
+            self.try_signature(
+                Some(main_sig),
+                name,
+                new_name,
+                &code,
+                &prefixes,
+                too_long,
+                true,
+            )
+            .await
+            .or(prev)
+        } else if let Some(target_sig) = target_sig {
             assert!(prev.is_some());
             let translated = self
                 .try_signature(
@@ -1825,7 +3619,10 @@ impl<'ast> Translator<'ast> {
             assert_eq!(target_sig.signature, f.signature);
             Some(translated)
         } else if self.config.try_multiple_signatures {
-            let mut sig_map = self.translate_signature(&code, new_name, &prefixes).await;
+            let sig = &func.type_signature;
+            let mut sig_map = self
+                .translate_signature(&code, new_name, &prefixes, sig.variadic)
+                .await;
             if sig_map.is_empty() {
                 if !self.config.quiet {
                     println!("Signature not translated: {}", new_name);
@@ -1833,7 +3630,7 @@ impl<'ast> Translator<'ast> {
                 self.try_signature(None, name, new_name, &code, &prefixes, too_long, false)
                     .await
             } else {
-                let param_len = func.type_signature.params.len();
+                let param_len = sig.params.len() + usize::from(sig.variadic);
                 if sig_map.keys().any(|sig| sig.params.len() <= param_len) {
                     sig_map.retain(|sig, _| sig.params.len() <= param_len);
                 }
@@ -1897,7 +3694,11 @@ impl<'ast> Translator<'ast> {
             if !self.config.quiet {
                 println!("Function not translated: {}", new_name);
             }
-            let code = format!("fn {}() {{todo!()}}", new_name);
+            let code = if let Some(main_sig) = &main_sig {
+                format!("{}{{todo!()}}", main_sig)
+            } else {
+                format!("fn {}() {{todo!()}}", new_name)
+            };
             let items = compiler::parse(&code).unwrap();
             TranslationResult {
                 items,
@@ -1907,6 +3708,7 @@ impl<'ast> Translator<'ast> {
                 failed: true,
                 proto: false,
                 types: vec![],
+                usage: TokenUsage::default(),
             }
         });
         translated.too_long = too_long;
@@ -1930,11 +3732,14 @@ impl<'ast> Translator<'ast> {
         translated
     }
 
+    /// Translates the signature of a function, keeping only the signatures taking the variable
+    /// arguments as `&[VaArg]` if it is variadic.
     async fn translate_signature(
         &self,
         code: &str,
         new_name: &str,
         prefixes: &DependencyPrefixes,
+        variadic: bool,
     ) -> BTreeMap<FunTySig, String> {
         let empty = vec![];
         let translation_prefix = if self.config.provide_signatures {
@@ -1973,7 +3778,12 @@ impl<'ast> Translator<'ast> {
             );
             let mut item_names = BTreeSet::new();
             item_names.insert(new_name.to_string());
-            let mut ctxt = FixContext::new(&prefixes.checking_prefix, sig, &item_names);
+            let mut ctxt = FixContext::new(
+                &prefixes.checking_prefix,
+                sig,
+                &item_names,
+                self.config.max_fixes,
+            );
             Self::fix_by_uses(&mut ctxt);
             let result = some_or!(ctxt.result, continue);
             if !result.passed() {
@@ -1981,6 +3791,14 @@ impl<'ast> Translator<'ast> {
             }
             let (parsed_name, info) = some_or!(compiler::parse_signature(&ctxt.code), continue);
             assert_eq!(&parsed_name, new_name);
+            if variadic && !takes_va_args(&info.signature_ty) {
+                tracing::info!(
+                    "translate_signature rejected ({})\n{}",
+                    new_name,
+                    info.signature
+                );
+                continue;
+            }
             sig_map.entry(info.signature_ty).or_insert(info.signature);
         }
         sig_map
@@ -2034,11 +3852,16 @@ impl<'ast> Translator<'ast> {
         )
         .unwrap();
         let item = compiler::parse_one(&translated).unwrap();
+        let f = item.as_function().unwrap();
         if let Some(sig) = sig {
-            if must_preserve && item.as_function().unwrap().signature != sig {
+            if must_preserve && f.signature != sig {
                 return None;
             }
         }
+        if self.functions[name].type_signature.variadic && !takes_va_args(&f.signature_ty) {
+            tracing::info!("try_signature rejected ({})\n{}", new_name, f.signature);
+            return None;
+        }
         let items = vec![item];
         let item_names: BTreeSet<_> = items.iter().map(|i| i.name.clone()).collect();
 
@@ -2050,6 +3873,7 @@ impl<'ast> Translator<'ast> {
             failed: false,
             proto: false,
             types: vec![],
+            usage: TokenUsage::default(),
         };
         tracing::info!(
             "try_signature translated ({})\n{}\n{}",
@@ -2063,6 +3887,7 @@ impl<'ast> Translator<'ast> {
             &prefixes.checking_prefix,
             translated_code.clone(),
             &item_names,
+            self.config.max_fixes,
         );
         let res = ctxt.result.as_ref()?;
         translated.stage = res.stage;
@@ -2126,30 +3951,37 @@ impl<'ast> Translator<'ast> {
     }
 
     async fn translate_recursive_functions(&self, names: BTreeSet<&'ast str>) {
+        {
+            let inner = self.inner.read().unwrap();
+            if names
+                .iter()
+                .all(|name| inner.translated_functions.contains_key(name))
+            {
+                return;
+            }
+        }
+
         if !self.config.quiet && names.len() > 1 {
             println!("{:?}", names);
         }
 
-        let mut cg: BTreeMap<_, BTreeSet<_>> = names
-            .iter()
-            .map(|name| {
-                let func = self.functions.get(name).unwrap();
-                let callees = func
-                    .callees
-                    .iter()
-                    .map(|callee| callee.node.name.as_str())
-                    .filter(|callee| names.contains(callee) && callee != name)
-                    .collect();
-                (*name, callees)
-            })
-            .collect();
+        let mut cg = self.call_graph(&names);
 
         let mut sig_map = BTreeMap::new();
         while !cg.is_empty() {
             if let Some((name, _)) = cg.iter().find(|(_, callees)| callees.is_empty()) {
                 let prev = self.remove_func(name);
                 let target_sig = sig_map.get(name);
-                let translated = self.translate_function(name, target_sig, prev).await;
+                // keeps the requests of the translation picked to break the cycle
+                let pick_usage = target_sig.and(prev.as_ref()).map(|prev| prev.usage);
+                let mut translated = track_usage(
+                    name.to_string(),
+                    self.translate_function(name, target_sig, prev),
+                )
+                .await;
+                if let Some(usage) = pick_usage {
+                    translated.usage += usage;
+                }
 
                 let mut inner = self.inner.write().unwrap();
                 inner.add_names(&translated);
@@ -2165,7 +3997,8 @@ impl<'ast> Translator<'ast> {
                 if !self.config.quiet {
                     println!("pick: {}", name);
                 }
-                let translated = self.translate_function(name, None, None).await;
+                let translated =
+                    track_usage(name.to_string(), self.translate_function(name, None, None)).await;
                 assert_eq!(translated.items.len(), 1);
                 let f = translated.items[0].as_function().unwrap();
                 sig_map.insert(name, f.clone());
@@ -2174,6 +4007,29 @@ impl<'ast> Translator<'ast> {
                 inner.translated_functions.insert(name, translated);
             }
         }
+
+        let inner = self.inner.read().unwrap();
+        for name in names {
+            self.save_term(
+                "functions",
+                name,
+                inner.translated_functions.get(name).unwrap(),
+            );
+        }
+    }
+
+    fn call_graph(&self, names: &BTreeSet<&'ast str>) -> BTreeMap<&'ast str, BTreeSet<&'ast str>> {
+        names
+            .iter()
+            .map(|name| {
+                let callees = self.function_edges[name]
+                    .iter()
+                    .copied()
+                    .filter(|callee| names.contains(callee) && callee != name)
+                    .collect();
+                (*name, callees)
+            })
+            .collect()
     }
 
     fn remove_func(&self, name: &str) -> Option<TranslationResult> {
@@ -2202,13 +4058,21 @@ impl<'ast> Translator<'ast> {
     pub async fn translate_functions(&self) {
         let mut function_elem_map = self.function_elem_map.clone();
         for funcs in function_elem_map.values_mut() {
-            funcs.retain(|f| self.functions.contains_key(f));
+            funcs.retain(|f| {
+                self.functions.contains_key(f)
+                    && self
+                        .selected
+                        .as_ref()
+                        .map_or(true, |s| s.functions.contains(f))
+            });
         }
         let mut graph = self.function_graph.clone();
         graph.retain(|id, _| !function_elem_map.get(id).unwrap().is_empty());
         for edges in graph.values_mut() {
             edges.retain(|succ| !function_elem_map.get(succ).unwrap().is_empty());
         }
+        self.translate_stubs().await;
+
         let mut futures = vec![];
 
         loop {
@@ -2245,6 +4109,88 @@ impl<'ast> Translator<'ast> {
     }
 }
 
+/// Prepends a note on the use of translated items to the code given to the language model.
+fn prepend_note(code: String, note: Option<String>) -> String {
+    match note {
+        Some(note) => format!("{}\n{}", comment_lines(&note, "//"), code),
+        None => code,
+    }
+}
+
+fn comment_lines(comment: &str, marker: &str) -> String {
+    comment
+        .lines()
+        .map(|line| {
+            if line.is_empty() {
+                marker.to_string()
+            } else {
+                format!("{} {}", marker, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Generates the methods reading and writing bit-fields through their backing integers.
+fn bit_field_accessors(name: &str, bit_fields: &BitFields) -> String {
+    let methods = bit_fields
+        .fields
+        .iter()
+        .map(|f| {
+            let backing = BitFields::backing_field(f.unit);
+            let bits = bit_fields.units[f.unit];
+            let offset = f.offset;
+            let mask = format!("{:#x}", (1u128 << f.width) - 1);
+            let (ty, get) = if f.boolean {
+                (
+                    "bool".to_string(),
+                    format!("self.{} >> {} & 1 != 0", backing, offset),
+                )
+            } else if f.signed {
+                let shift = bits - f.width;
+                (
+                    format!("i{}", f.bits),
+                    format!(
+                        "((self.{} << {}) as i{} >> {}) as i{}",
+                        backing,
+                        shift - offset,
+                        bits,
+                        shift,
+                        f.bits
+                    ),
+                )
+            } else {
+                (
+                    format!("u{}", f.bits),
+                    format!("(self.{} >> {} & {}) as u{}", backing, offset, mask, f.bits),
+                )
+            };
+            format!(
+                "    pub fn {name}(&self) -> {ty} {{\n        {get}\n    }}\n\n    \
+                 pub fn set_{setter}(&mut self, value: {ty}) {{\n        \
+                 self.{backing} = self.{backing} & !({mask} << {offset}) \
+                 | (value as u{bits} & {mask}) << {offset};\n    }}",
+                name = bit_field_getter(&f.name),
+                setter = f.name,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    format!("impl {} {{\n{}\n}}", name, methods)
+}
+
+/// Returns the name of the getter of a bit-field, which is a raw identifier if the field is
+/// named after a Rust keyword.
+fn bit_field_getter(name: &str) -> String {
+    if matches!(name, "crate" | "self" | "Self" | "super") {
+        format!("{}_", name)
+    } else if RUST_KEYWORDS.contains(name) {
+        format!("r#{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
 fn difference(s1: &str, s2: &str) -> String {
     let mut result = String::new();
     for (i, diff) in diff::lines(s1, s2).iter().enumerate() {
@@ -2261,8 +4207,645 @@ fn difference(s1: &str, s2: &str) -> String {
     result
 }
 
+async fn track_usage<F: Future<Output = TranslationResult>>(
+    item: String,
+    f: F,
+) -> TranslationResult {
+    let (mut translated, usage) = llm_client::track_usage(llm_client::with_item(item, f)).await;
+    translated.usage = usage;
+    translated
+}
+
+fn error_stats(
+    translated: &BTreeMap<&str, TranslationResult>,
+    transitive: &BTreeMap<&str, BTreeSet<&str>>,
+) -> ErrorStats {
+    let mut stats = ErrorStats::default();
+    for (name, t) in translated {
+        if t.proto {
+            stats.protos += 1;
+        } else if t.too_long {
+            stats.longs += 1;
+        } else if t.failed {
+            stats.fails += 1;
+        } else if t.errors > 0 {
+            stats.w_errors += 1;
+            stats.errors += t.errors;
+        } else {
+            stats.wo_errors += 1;
+            stats.wo_error_names.push(name.to_string());
+            let callees = &transitive[name];
+            if callees.iter().all(|c| {
+                let t = some_or!(translated.get(c), return true);
+                t.no_error()
+            }) {
+                stats.no_trans_errors += 1;
+                stats.no_trans_error_names.push(name.to_string());
+            }
+        }
+    }
+    stats
+}
+
+fn prune_graph<T, F: Fn(&T) -> bool>(
+    graph: &mut BTreeMap<Id, BTreeSet<Id>>,
+    elem_map: &BTreeMap<Id, BTreeSet<T>>,
+    keep: F,
+) {
+    graph.retain(|id, _| elem_map.get(id).unwrap().iter().any(&keep));
+    let ids: BTreeSet<_> = graph.keys().copied().collect();
+    for edges in graph.values_mut() {
+        edges.retain(|id| ids.contains(id));
+    }
+}
+
+fn write_checkpoint<T: Serialize + ?Sized>(path: &Path, value: &T) {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_string(value).unwrap()).unwrap();
+    fs::rename(tmp, path).unwrap();
+}
+
+fn read_checkpoint_dir<T: for<'de> Deserialize<'de>>(dir: &Path) -> Vec<(String, T)> {
+    let entries = some_or!(fs::read_dir(dir).ok(), return vec![]);
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "json" {
+                return None;
+            }
+            let name = path.file_stem()?.to_str()?.to_string();
+            let value = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+/// Checks if the last parameter of a signature is `&[VaArg]`.
+fn takes_va_args(sig: &FunTySig) -> bool {
+    sig.params
+        .last()
+        .map_or(false, |t| t.to_string() == "&[VaArg]")
+}
+
+/// Converts an enumerator name to `CamelCase`, e.g., `COLOR_DARK_RED` to `ColorDarkRed`.
+fn variant_name(enumerator: &str) -> String {
+    let mut name = String::new();
+    for part in enumerator.split('_').filter(|p| !p.is_empty()) {
+        let upper = !part.chars().any(|c| c.is_lowercase());
+        let mut chars = part.chars();
+        name.extend(chars.next().unwrap().to_uppercase());
+        if upper {
+            name.extend(chars.flat_map(|c| c.to_lowercase()));
+        } else {
+            name.extend(chars);
+        }
+    }
+    if !name.starts_with(|c: char| c.is_alphabetic()) {
+        name.insert(0, 'V');
+    }
+    name
+}
+
+fn sanitize_name(s: &str) -> String {
+    let mut name: String = s
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+fn module_name(path: &str, existing: &BTreeSet<String>) -> String {
+    let stem = Path::new(path)
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    let mut name = sanitize_name(&stem);
+    if RESERVED_MODULE_NAMES.contains(name.as_str()) {
+        name += "_c";
+    }
+    let mut unique = name.clone();
+    let mut i = 1;
+    while existing.contains(&unique) {
+        unique = format!("{}_{}", name, i);
+        i += 1;
+    }
+    unique
+}
+
 lazy_static! {
     static ref KEYWORDS: BTreeSet<&'static str> = KEYWORDS_RAW.iter().copied().collect();
+    static ref RUST_KEYWORDS: BTreeSet<&'static str> = RUST_KEYWORDS_RAW.iter().copied().collect();
+    static ref RESERVED_MODULE_NAMES: BTreeSet<&'static str> =
+        RESERVED_MODULE_NAMES_RAW.iter().copied().collect();
+    static ref DEPENDENCIES: &'static str = {
+        let manifest = include_str!("../deps_crate/Cargo.toml");
+        let i = manifest.find("[dependencies]").unwrap();
+        &manifest[i..]
+    };
 }
 
 static KEYWORDS_RAW: [&str; 7] = ["main", "loop", "match", "where", "mod", "ref", "return"];
+
+/// The Rust keywords that are valid C identifiers.
+static RUST_KEYWORDS_RAW: [&str; 37] = [
+    "abstract", "as", "async", "await", "become", "box", "crate", "dyn", "false", "final", "fn",
+    "impl", "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub",
+    "ref", "self", "Self", "super", "trait", "true", "try", "type", "unsafe", "unsized", "use",
+    "virtual", "where", "yield",
+];
+
+static RESERVED_MODULE_NAMES_RAW: [&str; 44] = [
+    "as",
+    "async",
+    "await",
+    "break",
+    "const",
+    "continue",
+    "core",
+    "crate",
+    "dyn",
+    "else",
+    "enum",
+    "extern",
+    "false",
+    "fn",
+    "for",
+    "if",
+    "impl",
+    "in",
+    "let",
+    "lib",
+    "libc",
+    "loop",
+    "main",
+    "match",
+    "mod",
+    "move",
+    "mut",
+    "once_cell",
+    "pub",
+    "ref",
+    "return",
+    "self",
+    "static",
+    "std",
+    "struct",
+    "super",
+    "trait",
+    "true",
+    "type",
+    "unsafe",
+    "use",
+    "where",
+    "while",
+    "_",
+];
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+
+    struct NoClient;
+
+    #[async_trait]
+    impl LanguageModel for NoClient {
+        fn requests(&self) -> usize {
+            0
+        }
+
+        fn request_tokens(&self) -> usize {
+            0
+        }
+
+        fn response_tokens(&self) -> usize {
+            0
+        }
+
+        fn response_time(&self) -> f32 {
+            0.0
+        }
+
+        fn cache_misses(&self) -> Vec<CacheMiss> {
+            vec![]
+        }
+
+        async fn rename_type(&self, _: &str) -> String {
+            unreachable!()
+        }
+
+        async fn translate_type(&self, _: &str, _: &str, _: &[String]) -> Option<String> {
+            unreachable!()
+        }
+
+        async fn rename_variable(&self, _: &str) -> String {
+            unreachable!()
+        }
+
+        async fn translate_variable(&self, _: &str, _: &[String]) -> Option<String> {
+            unreachable!()
+        }
+
+        async fn rename_function(&self, _: &str) -> String {
+            unreachable!()
+        }
+
+        async fn translate_signature(
+            &self,
+            _: &str,
+            _: &str,
+            _: &[String],
+            _: usize,
+        ) -> Vec<String> {
+            unreachable!()
+        }
+
+        async fn translate_function(
+            &self,
+            _: &str,
+            _: Option<&str>,
+            _: &[String],
+        ) -> Option<String> {
+            unreachable!()
+        }
+
+        async fn translate_macro(&self, _: &str, _: &str, _: &str, _: &[String]) -> Option<String> {
+            unreachable!()
+        }
+
+        async fn fix(&self, _: &str, _: &str) -> Option<String> {
+            unreachable!()
+        }
+
+        async fn compare(&self, _: &str, _: &str) -> std::cmp::Ordering {
+            unreachable!()
+        }
+    }
+
+    fn translator(program: &Program) -> Translator<'_> {
+        let config = Config {
+            max_tokens: 1000,
+            try_multiple_signatures: false,
+            num_signatures: 1,
+            provide_signatures: false,
+            fix_errors: false,
+            consider_stages: false,
+            max_fixes: 0,
+            recover_macros: false,
+            prompt_comments: false,
+            provenance_comments: false,
+            quiet: true,
+        };
+        Translator::new(program, Box::new(NoClient), config)
+    }
+
+    const CODE: &str = "struct s { int x; };
+struct t { struct s s; };
+typedef struct t t_t;
+struct u { int y; };
+int g;
+int h;
+int leaf(struct s *p) { return p->x + g; }
+int mid(t_t *p) { return leaf(&p->s) + h; }
+int top(void) { return mid(0); }
+int other(struct u *p) { return p->y; }
+";
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("foo", "foo"));
+        assert!(!glob_match("foo", "foobar"));
+        assert!(glob_match("foo*", "foobar"));
+        assert!(glob_match("*.c", "src/main.c"));
+        assert!(!glob_match("*.c", "src/main.h"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(glob_match("*b*d", "abcbd"));
+        assert!(!glob_match("*bar", "foobaz"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test]
+    fn test_variant_name() {
+        assert_eq!(variant_name("RED"), "Red");
+        assert_eq!(variant_name("COLOR_DARK_RED"), "ColorDarkRed");
+        assert_eq!(variant_name("__COLOR_RED__"), "ColorRed");
+        assert_eq!(variant_name("kDarkRed"), "KDarkRed");
+        assert_eq!(variant_name("Mode_readOnly"), "ModeReadOnly");
+        assert_eq!(variant_name("2D"), "V2d");
+    }
+
+    #[test]
+    fn test_select_function() {
+        let program = Program::from_code(CODE);
+        let mut translator = translator(&program);
+        translator.select(&Selection {
+            functions: vec!["m*".to_string()],
+            ..Selection::default()
+        });
+        let selected = translator.selected.as_ref().unwrap();
+        assert_eq!(selected.functions, BTreeSet::from(["mid"]));
+        assert_eq!(selected.stubs, BTreeSet::from(["leaf"]));
+        assert_eq!(selected.variables, BTreeSet::from(["h"]));
+        assert!(selected.protos.is_empty());
+        assert_eq!(
+            selected.types,
+            BTreeSet::from([
+                CustomType::mk_typedef("t_t"),
+                CustomType::mk_struct("t"),
+                CustomType::mk_struct("s"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_select_file_and_type() {
+        let program = Program::from_code(CODE);
+        let mut translator = translator(&program);
+        translator.select(&Selection {
+            files: vec!["*.c".to_string()],
+            ..Selection::default()
+        });
+        let selected = translator.selected.as_ref().unwrap();
+        assert_eq!(selected.functions.len(), 4);
+        assert!(selected.stubs.is_empty());
+        assert_eq!(selected.variables, BTreeSet::from(["g", "h"]));
+
+        let mut translator = self::translator(&program);
+        translator.select(&Selection {
+            types: vec!["u".to_string()],
+            ..Selection::default()
+        });
+        let selected = translator.selected.as_ref().unwrap();
+        assert!(selected.functions.is_empty());
+        assert_eq!(selected.types, BTreeSet::from([CustomType::mk_struct("u")]));
+
+        let mut translator = self::translator(&program);
+        translator.select(&Selection::default());
+        assert!(translator.selected.is_none());
+    }
+
+    #[test]
+    fn test_prune_graph() {
+        let cg = BTreeMap::from([
+            ("a", BTreeSet::from(["b"])),
+            ("b", BTreeSet::from(["c"])),
+            ("c", BTreeSet::new()),
+            ("d", BTreeSet::from(["d"])),
+        ]);
+        let (mut graph, elem_map) = graph::compute_sccs(&cg);
+        prune_graph(&mut graph, &elem_map, |x| *x != "b" && *x != "d");
+        let elems: BTreeSet<_> = graph
+            .keys()
+            .flat_map(|id| elem_map.get(id).unwrap())
+            .copied()
+            .collect();
+        assert_eq!(elems, BTreeSet::from(["a", "c"]));
+        assert!(graph.values().all(|edges| edges.is_empty()));
+    }
+
+    #[test]
+    fn test_prompt_comments() {
+        let program = Program::from_code(CODE);
+        let mut translator = translator(&program);
+        let code = "int g;".to_string();
+        assert_eq!(
+            translator.with_comment(code.clone(), Some("Counter.")),
+            code
+        );
+
+        translator.config.prompt_comments = true;
+        assert_eq!(
+            translator.with_comment(code.clone(), Some("Counter.\n\nShared.")),
+            "// Counter.\n//\n// Shared.\nint g;"
+        );
+        assert_eq!(translator.with_comment(code.clone(), None), code);
+    }
+
+    #[test]
+    fn test_bit_field_accessors() {
+        let program = Program::from_code("struct s { unsigned type : 3, self : 1, x : 4; };\n");
+        let structs = program.structs();
+        let bit_fields = structs["s"].bit_fields.as_ref().unwrap();
+        let code = bit_field_accessors("S", bit_fields);
+        assert!(code.contains("pub fn r#type(&self) -> u32"));
+        assert!(code.contains("pub fn set_type(&mut self, value: u32)"));
+        assert!(code.contains("pub fn self_(&self) -> u32"));
+        assert!(code.contains("pub fn x(&self) -> u32"));
+        let code = format!("struct S {{ bitfield_0: u32 }}\n{}\nfn main() {{}}", code);
+        let result = compiler::type_check(&code).unwrap();
+        assert!(result.passed());
+    }
+
+    #[test]
+    fn test_bit_field_note() {
+        let program = Program::from_code(
+            "struct s { unsigned a : 3; };\nint f(struct s *p) { return p->a; }\n",
+        );
+        let mut translator = translator(&program);
+        let ty = CustomType {
+            name: "s",
+            sort: TypeSort::Struct,
+        };
+        translator.new_type_names.insert(ty, "S".to_string());
+        let structs = program.structs();
+        let bit_fields = structs["s"].bit_fields.as_ref().unwrap();
+        let mut items = compiler::parse("struct S { bitfield_0: u32 }").unwrap();
+        items[0].code += &bit_field_accessors("S", bit_fields);
+        let translated = TranslationResult {
+            items,
+            stage: compiler::MAX_STAGE,
+            errors: 0,
+            too_long: false,
+            failed: false,
+            proto: false,
+            types: vec![],
+            usage: TokenUsage::default(),
+        };
+        translator
+            .inner
+            .write()
+            .unwrap()
+            .translated_types
+            .insert(ty, translated);
+        let (code, _) = translator.function_code(&translator.functions["f"], "f");
+        assert!(
+            code.starts_with("// The bit-fields of `S` are accessed with `a()`/`set_a(value)`.")
+        );
+    }
+
+    #[test]
+    fn test_macro_uses() {
+        let square = Macro {
+            name: "SQUARE".to_string(),
+            params: Some(vec!["x".to_string()]),
+            body: "((x) * (x))".to_string(),
+            path: "test.c".to_string(),
+            location: None,
+        };
+        let size = Macro {
+            name: "SIZE".to_string(),
+            params: None,
+            body: "16".to_string(),
+            path: "test.c".to_string(),
+            location: None,
+        };
+        let code = "int g;\nint f(int x) { return ((g + x) * (g + x)) + 16; }\n";
+        let program = Program::from_code(code);
+        let mut translator = translator(&program);
+        translator.macros.insert("SQUARE", &square);
+        translator.macros.insert("SIZE", &size);
+        translator.new_term_names.insert("g", "G".to_string());
+        translator
+            .new_term_names
+            .insert("SQUARE", "square".to_string());
+        translator.new_term_names.insert("SIZE", "SIZE".to_string());
+        let start = code.find("((g").unwrap();
+        let end = code.find(" + 16").unwrap();
+        let size_start = end + 3;
+        let f = translator.functions.get_mut("f").unwrap();
+        f.macros = vec![
+            c_parser::MacroUse {
+                name: "SQUARE",
+                span: Span::span(start, end),
+                args: vec![Span::span(start + 2, start + 7)],
+            },
+            c_parser::MacroUse {
+                name: "SIZE",
+                span: Span::span(size_start, size_start + 2),
+                args: vec![],
+            },
+        ];
+        let (code, _) = translator.function_code(&translator.functions["f"], "f");
+        assert_eq!(code, "int f(int x) { return ((G + x) * (G + x)) + 16; }");
+
+        let translated = TranslationResult {
+            items: vec![],
+            stage: compiler::MAX_STAGE,
+            errors: 0,
+            too_long: false,
+            failed: false,
+            proto: false,
+            types: vec![],
+            usage: TokenUsage::default(),
+        };
+        let mut inner = translator.inner.write().unwrap();
+        inner.translated_macros.insert("SQUARE", translated.clone());
+        inner.translated_macros.insert("SIZE", translated);
+        drop(inner);
+        let (code, _) = translator.function_code(&translator.functions["f"], "f");
+        assert_eq!(code, "int f(int x) { return square(G + x) + SIZE; }");
+    }
+
+    #[test]
+    fn test_variadic_preamble() {
+        let program = Program::from_code("int f(int x) { return x; }\n");
+        assert!(!translator(&program).preamble.contains(VA_ARG));
+
+        let program = Program::from_code("int f(int n, ...) { return n; }\n");
+        assert!(translator(&program).preamble.contains(VA_ARG));
+
+        let program = Program::from_code(
+            "int log_msg(const char *fmt, ...);\nint f(void) { return log_msg(\"%d\", 1); }\n",
+        );
+        assert!(translator(&program).preamble.contains(VA_ARG));
+    }
+
+    #[test]
+    fn test_variadic_note() {
+        let program = Program::from_code(
+            "int log_msg(const char *fmt, ...);
+int sum(int n, ...) { return n; }
+void (*logger)(const char *, ...);
+int f(void) { logger(\"x\"); return sum(2, 1, 2) + log_msg(\"%d\", 1); }
+",
+        );
+        let mut translator = translator(&program);
+        for name in ["log_msg", "sum", "logger"] {
+            translator.new_term_names.insert(name, name.to_string());
+        }
+        let f = &translator.functions["f"];
+        assert!(translator.uses_va_args(f));
+        assert_eq!(
+            translator.variadic_callees(f),
+            BTreeSet::from(["log_msg", "logger", "sum"])
+        );
+        let note = translator.variadic_note(f).unwrap();
+        assert!(!note.contains(VARIADIC_NOTE));
+        assert!(note.contains("`logger` takes its variable arguments"));
+        assert_eq!(
+            translator.variadic_note(&translator.functions["sum"]),
+            Some(VARIADIC_NOTE.to_string())
+        );
+
+        let (code, _) = translator.function_code(f, "f");
+        assert!(code.starts_with("// `log_msg` takes its variable arguments"));
+    }
+
+    #[test]
+    fn test_takes_va_args() {
+        let sig = |code: &str| {
+            let items = compiler::parse(&format!("enum VaArg {{}}\n{}", code)).unwrap();
+            let f = items.iter().find_map(|i| i.as_function()).unwrap();
+            f.signature_ty.clone()
+        };
+        assert!(takes_va_args(&sig("fn f(n: i32, args: &[VaArg]) {}")));
+        assert!(!takes_va_args(&sig("fn f(n: i32) {}")));
+        assert!(!takes_va_args(&sig("fn f(args: &[VaArg], n: i32) {}")));
+        assert!(!takes_va_args(&sig("fn f(args: &mut [VaArg]) {}")));
+    }
+
+    #[test]
+    fn test_dispatch_table_sccs() {
+        let program = Program::from_code(
+            "int apply(int (*f)(int), int x) { return f(x); }
+int inc(int x) { return x + 1; }
+int twice(int x) { return apply(inc, apply(inc, x)); }
+int (*handlers[])(int) = { inc, twice };
+int dispatch(int i, int x) { return handlers[i](x); }
+",
+        );
+        let translator = translator(&program);
+        assert!(translator
+            .function_elem_map
+            .values()
+            .all(|set| set.len() == 1));
+
+        let id = |name| {
+            translator
+                .function_elem_map
+                .iter()
+                .find_map(|(id, set)| set.contains(name).then_some(*id))
+                .unwrap()
+        };
+        let graph = &translator.function_graph;
+        assert!(graph[&id("twice")].contains(&id("apply")));
+        assert!(graph[&id("apply")].contains(&id("inc")));
+        assert!(!graph[&id("apply")].contains(&id("twice")));
+        assert!(graph[&id("dispatch")].contains(&id("twice")));
+    }
+
+    #[test]
+    fn test_function_pointer_note() {
+        let program = Program::from_code(
+            "int inc(int x) { return x + 1; }
+int (*handlers[])(int) = { inc };
+",
+        );
+        let mut translator = translator(&program);
+        translator.new_term_names.insert("inc", "inc".to_string());
+        let (code, _) = translator.function_code(&translator.functions["inc"], "inc");
+        assert!(code.starts_with("// `inc` is used as a function pointer: "));
+    }
+}